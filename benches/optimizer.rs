@@ -0,0 +1,52 @@
+use brainfuck::{BrainfuckBuilder, OptLevel};
+use criterion::{
+    criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion,
+    Throughput,
+};
+use std::io;
+
+// Prints the squares from 0 to 10000, by Daniel B. Cristofani.
+const SQUARES: &str = include_str!("programs/squares.b");
+
+// Sieves and prints the primes up to 255.
+const SIEVE: &str = include_str!("programs/sieve.b");
+
+fn optimizer_levels(c: &mut Criterion) {
+    let mut group = c.benchmark_group("optimizer");
+    group.sample_size(20);
+
+    for (name, program) in &[("squares", SQUARES), ("sieve", SIEVE)] {
+        let build = |level| {
+            BrainfuckBuilder::new(program)
+                .opt_level(level)
+                .build()
+                .unwrap()
+        };
+
+        // Count commands as the unoptimized program runs them, so both levels
+        // report the same amount of work.
+        let mut plain = build(OptLevel::None);
+        plain.run(&mut io::empty(), &mut io::sink()).unwrap();
+        group.throughput(Throughput::Elements(plain.step_count()));
+
+        for &level in &[OptLevel::None, OptLevel::Full] {
+            let id = BenchmarkId::new(format!("{:?}", level), name);
+            let machine = build(level);
+
+            group.bench_function(id, |b| {
+                b.iter_batched(
+                    || machine.clone(),
+                    |mut brainfuck| {
+                        brainfuck.run(&mut io::empty(), &mut io::sink()).unwrap()
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, optimizer_levels);
+criterion_main!(benches);
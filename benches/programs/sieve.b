@@ -0,0 +1,18 @@
+>+>>>>+<<<<<++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++[>+>>>>+[->+>+<<]>>[-<
+<+>>]<---------->+<[[-]>-<]>[<<[-]<+[->>>>+<<+<<]>>[-<<+>>]>>----------<
+<+>>[[-]<<->>]<<[<<[-]<+>>>[-]]>[-]]<<<<<<[->>>>>>>>>>>>>>+<<<<<<<<<<<<<
++<]>[-<+>]>>>>>>>>>>>>>[->>>>>>>+<<<<<<<]>[->>>>>>>+<<<<<<<]<<<<<<>>>>>>
+>[>>->+<[>-<[->>+<<]]>>[-<<+>>]<[>>>+<<<<<[->+>>+<<<]>>>[-<<<+>>>]<-]<<<
+>>>>>[->>>>>>>+<<<<<<<]>[->>>>>>>+<<<<<<<]<<<<<<>>>>>>>]>>>+>>>[[-]<<<->
+>>]<<<[<<<+>>>>>[-<<<<+>+>>>]>+<<<-]>>[-]<<<<<>>>>>>[-<<<<<<<+>>>>>>>]<<
+<<<<<<<<<<<[>>>>>>[-<<<<<<<+>>>>>>>]<<<<<<<<<<<<<]>>>>>>[<<<<<<<<<<<<<[-
+>>>+>+<<<<]>>>>[-<<<<+>>>>]<[[-]>>+<<]>>[-<<+>+>]<[->+<]<[<<<+++++++++++
++++++++++++++++++++++++++++++++++++++.----------------------------------
+-------------->>>[-]]<<[->>+>+<<<]>>>[-<<<+>>>]>[-<<+>>]<<[<<+++++++++++
++++++++++++++++++++++++++++++++++++++.----------------------------------
+-------------->>[-]]<++++++++++++++++++++++++++++++++++++++++++++++++.--
+---------------------------------------------->++++++++++.[-]>>>>>>>>>>[
+-]]<<<<<<<<<<<<<<<<-]
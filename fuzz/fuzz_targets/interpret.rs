@@ -0,0 +1,31 @@
+//! Feeds arbitrary bytes to the parser and interpreter, looking for panics.
+//!
+//! The first half of the data is the program and the second half its input.
+//! Runs are capped at `MAX_STEPS` so that infinite loops don't stall the
+//! fuzzer. Run it from the repository root with:
+//!
+//!     cargo +nightly fuzz run interpret
+#![no_main]
+
+use brainfuck::{parse_with_warnings, Brainfuck};
+use libfuzzer_sys::fuzz_target;
+use std::io;
+
+const MAX_STEPS: u64 = 100_000;
+
+fuzz_target!(|data: &[u8]| {
+    let (program, mut input) = data.split_at(data.len() / 2);
+    let _ = parse_with_warnings(program);
+
+    let program = String::from_utf8_lossy(program);
+    let mut brainfuck = Brainfuck::new(&program);
+    let mut output = io::sink();
+
+    while !brainfuck.is_halted() && brainfuck.step_count() < MAX_STEPS {
+        if brainfuck.step(&mut input, &mut output).is_err() {
+            break;
+        }
+
+        assert!(brainfuck.tape_pointer() < brainfuck.tape_len());
+    }
+});
@@ -0,0 +1,25 @@
+use brainfuck::{parse_checked, ParseError};
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Error, LitStr};
+
+/// Expands `brainfuck!("++.")` to `brainfuck::Brainfuck::new("++.")`, failing
+/// to compile when the program's brackets are unbalanced.
+#[proc_macro]
+pub fn brainfuck(input: TokenStream) -> TokenStream {
+    let program = parse_macro_input!(input as LitStr);
+
+    let message = match parse_checked(program.value().as_bytes()) {
+        Ok(_) => return quote!(::brainfuck::Brainfuck::new(#program)).into(),
+        Err(ParseError::UnmatchedOpen(position)) => {
+            format!("unmatched '[' at byte {}", position)
+        }
+        Err(ParseError::UnmatchedClose(position)) => {
+            format!("unmatched ']' at byte {}", position)
+        }
+    };
+
+    Error::new(program.span(), message)
+        .to_compile_error()
+        .into()
+}
@@ -0,0 +1,7 @@
+#[test]
+fn ui() {
+    let cases = trybuild::TestCases::new();
+
+    cases.pass("tests/ui/balanced.rs");
+    cases.compile_fail("tests/ui/unbalanced.rs");
+}
@@ -0,0 +1,10 @@
+use brainfuck_macros::brainfuck;
+
+fn main() {
+    let mut brainfuck = brainfuck!("++++++++[>++++++++<-]>+.");
+    let mut output = Vec::new();
+
+    brainfuck.run(&mut std::io::empty(), &mut output).unwrap();
+
+    assert_eq!(b"A", &output[..]);
+}
@@ -0,0 +1,5 @@
+use brainfuck_macros::brainfuck;
+
+fn main() {
+    let _ = brainfuck!("+[>+");
+}
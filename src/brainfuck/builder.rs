@@ -0,0 +1,170 @@
+use crate::{
+    interpreter::{
+        BoundaryPolicy, Brainfuck, CellArith, FlushMode, OutputMode, PointerMode,
+        Result,
+    },
+    optimizer::{self, OptLevel, Semantics},
+    parser,
+};
+use std::sync::{atomic::AtomicBool, Arc};
+
+pub struct BrainfuckBuilder<'a> {
+    program: &'a str,
+    pointer_mode: PointerMode,
+    cell_arith: CellArith,
+    boundary_policy: BoundaryPolicy,
+    output_mode: OutputMode,
+    flush_mode: FlushMode,
+    opt_level: OptLevel,
+    max_loop_depth: Option<usize>,
+    stop_on_broken_pipe: bool,
+    interrupt: Option<Arc<AtomicBool>>,
+    profiling: bool,
+    record_input: bool,
+    initial_dp: usize,
+    rng_seed: u64,
+    name: Option<String>,
+}
+
+impl<'a> BrainfuckBuilder<'a> {
+    pub fn new(program: &'a str) -> BrainfuckBuilder<'a> {
+        BrainfuckBuilder {
+            program,
+            pointer_mode: PointerMode::default(),
+            cell_arith: CellArith::default(),
+            boundary_policy: BoundaryPolicy::default(),
+            output_mode: OutputMode::default(),
+            flush_mode: FlushMode::default(),
+            opt_level: OptLevel::default(),
+            max_loop_depth: None,
+            stop_on_broken_pipe: false,
+            interrupt: None,
+            profiling: false,
+            record_input: false,
+            initial_dp: 0,
+            rng_seed: 0,
+            name: None,
+        }
+    }
+
+    pub fn pointer_mode(mut self, mode: PointerMode) -> BrainfuckBuilder<'a> {
+        self.pointer_mode = mode;
+        self
+    }
+
+    pub fn cell_arith(mut self, arith: CellArith) -> BrainfuckBuilder<'a> {
+        self.cell_arith = arith;
+        self
+    }
+
+    pub fn boundary_policy(
+        mut self,
+        policy: BoundaryPolicy,
+    ) -> BrainfuckBuilder<'a> {
+        self.boundary_policy = policy;
+        self
+    }
+
+    pub fn output_mode(mut self, mode: OutputMode) -> BrainfuckBuilder<'a> {
+        self.output_mode = mode;
+        self
+    }
+
+    pub fn flush_mode(mut self, mode: FlushMode) -> BrainfuckBuilder<'a> {
+        self.flush_mode = mode;
+        self
+    }
+
+    pub fn opt_level(mut self, level: OptLevel) -> BrainfuckBuilder<'a> {
+        self.opt_level = level;
+        self
+    }
+
+    /// Shorthand for `opt_level`: `false` keeps one instruction per command.
+    pub fn optimize(self, enabled: bool) -> BrainfuckBuilder<'a> {
+        self.opt_level(if enabled {
+            OptLevel::Full
+        } else {
+            OptLevel::None
+        })
+    }
+
+    pub fn max_loop_depth(mut self, depth: usize) -> BrainfuckBuilder<'a> {
+        self.max_loop_depth = Some(depth);
+        self
+    }
+
+    pub fn stop_on_broken_pipe(mut self, enabled: bool) -> BrainfuckBuilder<'a> {
+        self.stop_on_broken_pipe = enabled;
+        self
+    }
+
+    pub fn interrupt(mut self, flag: Arc<AtomicBool>) -> BrainfuckBuilder<'a> {
+        self.interrupt = Some(flag);
+        self
+    }
+
+    pub fn profiling(mut self, enabled: bool) -> BrainfuckBuilder<'a> {
+        self.profiling = enabled;
+        self
+    }
+
+    pub fn record_input(mut self, enabled: bool) -> BrainfuckBuilder<'a> {
+        self.record_input = enabled;
+        self
+    }
+
+    pub fn initial_dp(mut self, dp: usize) -> BrainfuckBuilder<'a> {
+        self.initial_dp = dp;
+        self
+    }
+
+    /// Seeds the generator extensions draw from with `random_byte`.
+    pub fn rng_seed(mut self, seed: u64) -> BrainfuckBuilder<'a> {
+        self.rng_seed = seed;
+        self
+    }
+
+    /// Names the program in messages from `Brainfuck::describe`.
+    pub fn name<S: Into<String>>(mut self, name: S) -> BrainfuckBuilder<'a> {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Fails with `Error::TapeOverflow` if `initial_dp` is not on the tape.
+    pub fn build(self) -> Result<Brainfuck> {
+        let instructions = parser::parse(self.program.as_bytes());
+        let semantics = Semantics {
+            cell_arith: self.cell_arith,
+            pointer_mode: self.pointer_mode,
+            boundary_policy: self.boundary_policy,
+        };
+        let instructions =
+            optimizer::optimize_with(instructions, self.opt_level, semantics);
+
+        let len = instructions.len();
+        let mut brainfuck = Brainfuck::from_instructions(Vec::from(instructions));
+        brainfuck.pointer_mode = self.pointer_mode;
+        brainfuck.cell_arith = self.cell_arith;
+        brainfuck.boundary_policy = self.boundary_policy;
+        brainfuck.output_mode = self.output_mode;
+        brainfuck.flush_mode = self.flush_mode;
+        brainfuck.opt_level = self.opt_level;
+        brainfuck.max_loop_depth = self.max_loop_depth;
+        brainfuck.stop_on_broken_pipe = self.stop_on_broken_pipe;
+        brainfuck.interrupt = self.interrupt;
+        brainfuck.name = self.name;
+
+        if self.profiling {
+            brainfuck.profile = Some(vec![0; len]);
+        }
+        if self.record_input {
+            brainfuck.recording = Some(Vec::new());
+        }
+        brainfuck.seed_rng(self.rng_seed);
+        brainfuck.set_tape_pointer(self.initial_dp)?;
+        brainfuck.initial_dp = self.initial_dp;
+
+        Ok(brainfuck)
+    }
+}
@@ -0,0 +1,215 @@
+use crate::instruction::Instruction;
+use std::{collections::VecDeque, fmt};
+
+const ADD: u8 = 0;
+const SUB: u8 = 1;
+const RIGHT: u8 = 2;
+const LEFT: u8 = 3;
+const OUT: u8 = 4;
+const OUT_N: u8 = 5;
+const IN: u8 = 6;
+const OPEN: u8 = 7;
+const CLOSE: u8 = 8;
+const EXT: u8 = 9;
+const MOVE: u8 = 10;
+const OUT_DECIMAL: u8 = 11;
+const OUT_UNICODE: u8 = 12;
+const CLEAR: u8 = 13;
+const SWAP: u8 = 14;
+const SET: u8 = 15;
+const ADD_OFFSET: u8 = 16;
+const SUB_OFFSET: u8 = 17;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct InvalidBytecode {
+    pub offset: usize,
+}
+
+impl fmt::Display for InvalidBytecode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid bytecode at byte {}", self.offset)
+    }
+}
+
+/// Packs `instructions` as one opcode byte each, followed by LEB128 varints
+/// for their arguments. Offsets come first and are zigzag encoded.
+pub fn encode(instructions: &VecDeque<Instruction>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(instructions.len());
+
+    for instruction in instructions {
+        let (opcode, offset, arg) = match *instruction {
+            Instruction::Add(n) => (ADD, None, Some(u64::from(n))),
+            Instruction::Sub(n) => (SUB, None, Some(u64::from(n))),
+            Instruction::Right(n) => (RIGHT, None, Some(n as u64)),
+            Instruction::Left(n) => (LEFT, None, Some(n as u64)),
+            Instruction::Out => (OUT, None, None),
+            Instruction::OutN(n) => (OUT_N, None, Some(n as u64)),
+            Instruction::In => (IN, None, None),
+            Instruction::Open => (OPEN, None, None),
+            Instruction::Close => (CLOSE, None, None),
+            Instruction::Ext(c) => (EXT, None, Some(u64::from(c))),
+            Instruction::Move { offset } => (MOVE, Some(offset), None),
+            Instruction::OutDecimal => (OUT_DECIMAL, None, None),
+            Instruction::OutUnicode => (OUT_UNICODE, None, None),
+            Instruction::Clear => (CLEAR, None, None),
+            Instruction::Swap => (SWAP, None, None),
+            Instruction::Set(n) => (SET, None, Some(u64::from(n))),
+            Instruction::AddOffset { offset, n } => {
+                (ADD_OFFSET, Some(offset), Some(u64::from(n)))
+            }
+            Instruction::SubOffset { offset, n } => {
+                (SUB_OFFSET, Some(offset), Some(u64::from(n)))
+            }
+        };
+
+        bytes.push(opcode);
+
+        if let Some(offset) = offset {
+            let offset = offset as i64;
+            push_varint(&mut bytes, ((offset << 1) ^ (offset >> 63)) as u64);
+        }
+        if let Some(arg) = arg {
+            push_varint(&mut bytes, arg);
+        }
+    }
+
+    bytes
+}
+
+fn push_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Reads back the output of `encode`, failing at the first byte that doesn't
+/// fit: an unknown opcode, a truncated argument or one out of range.
+pub fn decode(bytes: &[u8]) -> Result<VecDeque<Instruction>, InvalidBytecode> {
+    let mut instructions = VecDeque::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let start = offset;
+        let invalid = || InvalidBytecode { offset: start };
+        let opcode = bytes[offset];
+        offset += 1;
+
+        let mut arg = || -> Result<u64, InvalidBytecode> {
+            let mut value = 0u64;
+
+            for shift in (0..64).step_by(7) {
+                let byte = *bytes.get(offset).ok_or_else(invalid)?;
+                offset += 1;
+                value |= u64::from(byte & 0x7f) << shift;
+
+                if byte & 0x80 == 0 {
+                    return Ok(value);
+                }
+            }
+
+            Err(invalid())
+        };
+
+        let instruction = match opcode {
+            ADD => Instruction::Add(narrow(arg()?).ok_or_else(invalid)?),
+            SUB => Instruction::Sub(narrow(arg()?).ok_or_else(invalid)?),
+            RIGHT => Instruction::Right(narrow(arg()?).ok_or_else(invalid)?),
+            LEFT => Instruction::Left(narrow(arg()?).ok_or_else(invalid)?),
+            OUT => Instruction::Out,
+            OUT_N => Instruction::OutN(narrow(arg()?).ok_or_else(invalid)?),
+            IN => Instruction::In,
+            OPEN => Instruction::Open,
+            CLOSE => Instruction::Close,
+            EXT => Instruction::Ext(narrow(arg()?).ok_or_else(invalid)?),
+            MOVE => Instruction::Move {
+                offset: narrow(unzigzag(arg()?)).ok_or_else(invalid)?,
+            },
+            ADD_OFFSET => Instruction::AddOffset {
+                offset: narrow(unzigzag(arg()?)).ok_or_else(invalid)?,
+                n: narrow(arg()?).ok_or_else(invalid)?,
+            },
+            SUB_OFFSET => Instruction::SubOffset {
+                offset: narrow(unzigzag(arg()?)).ok_or_else(invalid)?,
+                n: narrow(arg()?).ok_or_else(invalid)?,
+            },
+            OUT_DECIMAL => Instruction::OutDecimal,
+            OUT_UNICODE => Instruction::OutUnicode,
+            CLEAR => Instruction::Clear,
+            SWAP => Instruction::Swap,
+            SET => Instruction::Set(narrow(arg()?).ok_or_else(invalid)?),
+            _ => return Err(invalid()),
+        };
+
+        instructions.push_back(instruction);
+    }
+
+    Ok(instructions)
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+fn narrow<T, U: std::convert::TryFrom<T>>(value: T) -> Option<U> {
+    U::try_from(value).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode, InvalidBytecode, RIGHT};
+    use crate::{
+        instruction::Instruction::*, optimizer::optimize, parser::parse,
+    };
+    use std::collections::VecDeque;
+
+    #[test]
+    fn round_trip_hello_world() {
+        let instructions = optimize(parse(
+            b"++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---\
+              .+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.\n",
+        ));
+        let bytes = encode(&instructions);
+
+        assert!(bytes.len() < instructions.len() * 2);
+        assert_eq!(Ok(instructions), decode(&bytes));
+    }
+
+    #[test]
+    fn round_trip_arguments() {
+        let instructions = VecDeque::from(vec![
+            Add(255),
+            Right(100_000),
+            OutN(usize::MAX),
+            Ext(b'@'),
+            Move { offset: -3 },
+            Move { offset: isize::MIN },
+            Move { offset: isize::MAX },
+            OutDecimal,
+            OutUnicode,
+            Clear,
+            Swap,
+            Set(200),
+            AddOffset { offset: -7, n: 9 },
+            SubOffset {
+                offset: 300,
+                n: 255,
+            },
+        ]);
+
+        assert_eq!(Ok(instructions.clone()), decode(&encode(&instructions)));
+        assert_eq!(
+            vec![RIGHT, 0xa0, 0x8d, 0x06],
+            encode(&VecDeque::from(vec![Right(100_000)]))
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(Err(InvalidBytecode { offset: 1 }), decode(&[4, 99]));
+        assert_eq!(Err(InvalidBytecode { offset: 0 }), decode(&[0, 0x80, 0x02]));
+        assert_eq!(Err(InvalidBytecode { offset: 2 }), decode(&[7, 8, 2, 0x80]));
+        assert_eq!(Ok(VecDeque::new()), decode(&[]));
+    }
+}
@@ -0,0 +1,145 @@
+use crate::instruction::Instruction;
+use std::{collections::VecDeque, fmt::Write};
+
+pub fn to_dot(instructions: &VecDeque<Instruction>) -> String {
+    let mut graph = Graph::default();
+
+    for i in instructions {
+        match i {
+            Instruction::Open => {
+                graph.flush();
+                graph.clusters += 1;
+                graph.line(&format!("subgraph cluster_{} {{", graph.clusters));
+                graph.depth += 1;
+                graph.line(&format!("label=\"loop {}\";", graph.clusters));
+
+                let open = graph.node("[");
+                graph.opens.push(open);
+            }
+            Instruction::Close => {
+                graph.flush();
+                let close = graph.node("]");
+
+                if let Some(open) = graph.opens.pop() {
+                    graph
+                        .edges
+                        .push(format!("n{} -> n{} [style=dashed];", open, close));
+                    graph
+                        .edges
+                        .push(format!("n{} -> n{} [style=dashed];", close, open));
+                    graph.depth -= 1;
+                    graph.line("}");
+                }
+            }
+            _ => graph.block.push(*i),
+        }
+    }
+
+    graph.flush();
+
+    while graph.opens.pop().is_some() {
+        graph.depth -= 1;
+        graph.line("}");
+    }
+
+    let mut dot = String::from("digraph brainfuck {\n    node [shape=box];\n");
+    dot.push_str(&graph.body);
+
+    for edge in &graph.edges {
+        let _ = writeln!(dot, "    {}", edge);
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+#[derive(Default)]
+struct Graph {
+    body: String,
+    edges: Vec<String>,
+    block: Vec<Instruction>,
+    opens: Vec<usize>,
+    previous: Option<usize>,
+    nodes: usize,
+    clusters: usize,
+    depth: usize,
+}
+
+impl Graph {
+    fn line(&mut self, line: &str) {
+        let _ = writeln!(
+            self.body,
+            "{:indent$}{}",
+            "",
+            line,
+            indent = 4 * (self.depth + 1)
+        );
+    }
+
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.nodes;
+        self.nodes += 1;
+
+        self.line(&format!("n{} [label=\"{}\"];", id, label));
+
+        if let Some(previous) = self.previous {
+            self.edges.push(format!("n{} -> n{};", previous, id));
+        }
+
+        self.previous = Some(id);
+        id
+    }
+
+    fn flush(&mut self) {
+        if self.block.is_empty() {
+            return;
+        }
+
+        let label = self
+            .block
+            .drain(..)
+            .map(|i| format!("{:?}", i))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let _ = self.node(&label);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::to_dot;
+    use crate::{optimizer::optimize, parser::parse};
+
+    fn dot(program: &str) -> String {
+        to_dot(&optimize(parse(program.as_bytes())))
+    }
+
+    #[test]
+    fn straight_line() {
+        assert_eq!(
+            "digraph brainfuck {\n    \
+             node [shape=box];\n    \
+             n0 [label=\"Add(3) Right(1) Out\"];\n\
+             }\n",
+            dot("+++>.")
+        );
+    }
+
+    #[test]
+    fn nested_loops() {
+        let dot = dot("+[>[--]<-]+[.]");
+
+        assert_eq!(3, dot.matches("subgraph cluster_").count());
+        assert!(dot.contains("label=\"Sub(2)\""));
+        assert_eq!(6, dot.matches("[style=dashed]").count());
+    }
+
+    #[test]
+    fn unbalanced() {
+        let dot = dot("][+");
+
+        assert_eq!(1, dot.matches("subgraph cluster_").count());
+        assert_eq!(dot.matches('{').count(), dot.matches('}').count());
+    }
+}
@@ -1,3 +1,5 @@
+use std::{fmt, str::FromStr};
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Instruction {
     Add(u8),
@@ -5,7 +7,245 @@ pub enum Instruction {
     Right(usize),
     Left(usize),
     Out,
+    OutN(usize),
     In,
     Open,
     Close,
+    Ext(u8),
+    Move { offset: isize },
+    OutDecimal,
+    OutUnicode,
+    Clear,
+    Swap,
+    Set(u8),
+    AddOffset { offset: isize, n: u8 },
+    SubOffset { offset: isize, n: u8 },
+}
+
+impl Instruction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::Add(_) => "Add",
+            Instruction::Sub(_) => "Sub",
+            Instruction::Right(_) => "Right",
+            Instruction::Left(_) => "Left",
+            Instruction::Out => "Out",
+            Instruction::OutN(_) => "OutN",
+            Instruction::In => "In",
+            Instruction::Open => "Open",
+            Instruction::Close => "Close",
+            Instruction::Ext(_) => "Ext",
+            Instruction::Move { .. } => "Move",
+            Instruction::OutDecimal => "OutDecimal",
+            Instruction::OutUnicode => "OutUnicode",
+            Instruction::Clear => "Clear",
+            Instruction::Swap => "Swap",
+            Instruction::Set(_) => "Set",
+            Instruction::AddOffset { .. } => "AddOffset",
+            Instruction::SubOffset { .. } => "SubOffset",
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Add(n) => write!(f, "ADD {}", n),
+            Instruction::Sub(n) => write!(f, "SUB {}", n),
+            Instruction::Right(n) => write!(f, "RIGHT {}", n),
+            Instruction::Left(n) => write!(f, "LEFT {}", n),
+            Instruction::Out => write!(f, "OUT"),
+            Instruction::OutN(n) => write!(f, "OUT {}", n),
+            Instruction::In => write!(f, "IN"),
+            Instruction::Open => write!(f, "OPEN"),
+            Instruction::Close => write!(f, "CLOSE"),
+            Instruction::Ext(c) => write!(f, "EXT {}", char::from(*c)),
+            Instruction::Move { offset } => write!(f, "MOVE {}", offset),
+            Instruction::OutDecimal => write!(f, "OUTDEC"),
+            Instruction::OutUnicode => write!(f, "OUTUNI"),
+            Instruction::Clear => write!(f, "CLEAR"),
+            Instruction::Swap => write!(f, "SWAP"),
+            Instruction::Set(n) => write!(f, "SET {}", n),
+            Instruction::AddOffset { offset, n } => {
+                write!(f, "ADD {}@{}", n, offset)
+            }
+            Instruction::SubOffset { offset, n } => {
+                write!(f, "SUB {}@{}", n, offset)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InvalidInstruction {
+    pub line: usize,
+    pub text: String,
+}
+
+impl fmt::Display for InvalidInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid instruction `{}` on line {}",
+            self.text, self.line
+        )
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = InvalidInstruction;
+
+    fn from_str(s: &str) -> Result<Instruction, InvalidInstruction> {
+        let invalid = || InvalidInstruction {
+            line: 1,
+            text: s.to_string(),
+        };
+        let mut words = s.split_whitespace();
+        let mnemonic = words.next().ok_or_else(invalid)?;
+        let arg = words.next();
+
+        if words.next().is_some() {
+            return Err(invalid());
+        }
+
+        fn number<T: FromStr>(arg: Option<&str>) -> Option<T> {
+            arg.and_then(|arg| arg.parse().ok())
+        }
+
+        let at = arg.and_then(|arg| {
+            let (n, offset) = arg.split_once('@')?;
+            Some((number(Some(n))?, number(Some(offset))?))
+        });
+
+        let instruction = match (mnemonic, arg) {
+            ("ADD", Some(arg)) if arg.contains('@') => {
+                at.map(|(n, offset)| Instruction::AddOffset { offset, n })
+            }
+            ("SUB", Some(arg)) if arg.contains('@') => {
+                at.map(|(n, offset)| Instruction::SubOffset { offset, n })
+            }
+            ("ADD", _) => number(arg).map(Instruction::Add),
+            ("SUB", _) => number(arg).map(Instruction::Sub),
+            ("RIGHT", _) => number(arg).map(Instruction::Right),
+            ("LEFT", _) => number(arg).map(Instruction::Left),
+            ("OUT", None) => Some(Instruction::Out),
+            ("OUT", _) => number(arg).map(Instruction::OutN),
+            ("IN", None) => Some(Instruction::In),
+            ("OPEN", None) => Some(Instruction::Open),
+            ("CLOSE", None) => Some(Instruction::Close),
+            ("EXT", Some(c)) if c.len() == 1 => {
+                Some(Instruction::Ext(c.as_bytes()[0]))
+            }
+            ("MOVE", _) => number(arg).map(|offset| Instruction::Move { offset }),
+            ("OUTDEC", None) => Some(Instruction::OutDecimal),
+            ("OUTUNI", None) => Some(Instruction::OutUnicode),
+            ("CLEAR", None) => Some(Instruction::Clear),
+            ("SWAP", None) => Some(Instruction::Swap),
+            ("SET", _) => number(arg).map(Instruction::Set),
+            _ => None,
+        };
+
+        instruction.ok_or_else(invalid)
+    }
+}
+
+/// Writes `instructions` one per line in their `Display` form.
+pub fn disassemble(instructions: &[Instruction]) -> String {
+    instructions.iter().map(|i| format!("{}\n", i)).collect()
+}
+
+/// Reads back the output of `disassemble`, skipping blank lines.
+pub fn assemble(src: &str) -> Result<Vec<Instruction>, InvalidInstruction> {
+    src.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            line.parse()
+                .map_err(|err: InvalidInstruction| InvalidInstruction {
+                    line: index + 1,
+                    ..err
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        assemble, disassemble, Instruction, Instruction::*, InvalidInstruction,
+    };
+
+    #[test]
+    fn display() {
+        assert_eq!("ADD 3", Add(3).to_string());
+        assert_eq!("SUB 255", Sub(255).to_string());
+        assert_eq!("RIGHT 1", Right(1).to_string());
+        assert_eq!("LEFT 40", Left(40).to_string());
+        assert_eq!("OUT", Out.to_string());
+        assert_eq!("OUT 4", OutN(4).to_string());
+        assert_eq!("IN", In.to_string());
+        assert_eq!("EXT @", Ext(b'@').to_string());
+        assert_eq!("MOVE -2", Move { offset: -2 }.to_string());
+        assert_eq!("OUTDEC", OutDecimal.to_string());
+        assert_eq!("OUTUNI", OutUnicode.to_string());
+        assert_eq!("CLEAR", Clear.to_string());
+        assert_eq!("SWAP", Swap.to_string());
+        assert_eq!("SET 5", Set(5).to_string());
+        assert_eq!("ADD 3@-2", AddOffset { offset: -2, n: 3 }.to_string());
+        assert_eq!("SUB 1@4", SubOffset { offset: 4, n: 1 }.to_string());
+        assert_eq!("OPEN", Open.to_string());
+        assert_eq!("CLOSE", Close.to_string());
+    }
+
+    #[test]
+    fn parse_display() {
+        let instructions = vec![
+            Add(3),
+            Sub(255),
+            Right(1),
+            Left(40),
+            Out,
+            OutN(4),
+            In,
+            Open,
+            Close,
+            Ext(b'@'),
+            Move { offset: -2 },
+            OutDecimal,
+            OutUnicode,
+            Clear,
+            Swap,
+            Set(5),
+            AddOffset { offset: -2, n: 3 },
+            SubOffset { offset: 4, n: 1 },
+        ];
+
+        for instruction in instructions {
+            assert_eq!(Ok(instruction), instruction.to_string().parse());
+        }
+    }
+
+    #[test]
+    fn assemble_round_trip() {
+        let instructions = vec![Add(2), Open, Sub(1), Move { offset: 1 }, Close];
+
+        assert_eq!(
+            Ok(instructions.clone()),
+            assemble(&disassemble(&instructions))
+        );
+    }
+
+    #[test]
+    fn assemble_errors() {
+        assert_eq!(
+            Err(InvalidInstruction {
+                line: 3,
+                text: String::from("ADD 256"),
+            }),
+            assemble("ADD 1\n\nADD 256\n")
+        );
+        assert!("OPEN 1".parse::<Instruction>().is_err());
+        assert!("JUMP".parse::<Instruction>().is_err());
+        assert!("ADD 1 2".parse::<Instruction>().is_err());
+    }
 }
@@ -1,303 +1,3597 @@
-use crate::{instruction::Instruction, optimizer, parser};
-use std::{collections::VecDeque, io, ops::Range};
+use crate::{
+    instruction::Instruction,
+    optimizer::{self, Node, OptLevel, Semantics},
+    parser::{self, Dialect, ParseError, WordDialect},
+};
+use std::{
+    cmp,
+    collections::{HashMap, VecDeque},
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    mem,
+    ops::Range,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 const TAPE_SIZE: usize = 30_000;
+const TIMEOUT_CHECK_INTERVAL: u64 = 65_536;
+const MAX_ZERO_WRITES: usize = 16;
+const SEED_MIX: u64 = 0x9e37_79b9_7f4a_7c15;
 
-pub type Result = std::result::Result<(), Error>;
+pub type Result<T = ()> = std::result::Result<T, Error>;
 
+pub type ExtHandler = Arc<dyn Fn(&mut Brainfuck) + Send + Sync>;
+
+#[derive(Clone)]
 pub struct Brainfuck {
-    instructions: VecDeque<Instruction>,
+    instructions: Arc<[Instruction]>,
+    jumps: Arc<[usize]>,
+    ip: usize,
+    printed: usize,
+    tape: Vec<u8>,
+    dp: usize,
+    origin: usize,
+    high_water: usize,
+    steps: u64,
+    last_write: Option<(usize, u8)>,
+    stack: Vec<usize>,
+    pub(crate) pointer_mode: PointerMode,
+    pub(crate) cell_arith: CellArith,
+    pub(crate) boundary_policy: BoundaryPolicy,
+    pub(crate) output_mode: OutputMode,
+    pub(crate) flush_mode: FlushMode,
+    cell_format: CellFormat,
+    pub(crate) opt_level: OptLevel,
+    pub(crate) max_loop_depth: Option<usize>,
+    pub(crate) stop_on_broken_pipe: bool,
+    pub(crate) interrupt: Option<Arc<AtomicBool>>,
+    pub(crate) profile: Option<Vec<u64>>,
+    pub(crate) recording: Option<Vec<u8>>,
+    replaying: Option<VecDeque<u8>>,
+    supplied: Option<u8>,
+    deadline: Option<Instant>,
+    extensions: HashMap<u8, ExtHandler>,
+    rng: u64,
+    pub(crate) name: Option<String>,
+    pub(crate) initial_dp: usize,
+}
+
+const DEBUG_TAPE_RADIUS: usize = 8;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Snapshot {
+    tape: Vec<u8>,
     ip: usize,
-    tape: [u8; TAPE_SIZE],
     dp: usize,
+    origin: usize,
     stack: Vec<usize>,
 }
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum RunState {
+    Halted,
+    Suspended,
+}
+
+/// A parsed, optimized and linked program. Cloning one, or starting machines
+/// from it, shares its instructions instead of copying them.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompiledProgram {
+    instructions: Arc<[Instruction]>,
+    jumps: Arc<[usize]>,
+}
+
+impl CompiledProgram {
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+}
+
+pub struct OutputIter<R> {
+    brainfuck: Brainfuck,
+    input: R,
+    pending: VecDeque<u8>,
+    failed: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum PointerMode {
+    #[default]
+    Clamp,
+    Wrap,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum CellArith {
+    #[default]
+    Wrap,
+    Saturate,
+}
+
+/// When to flush the output writer after the program prints something.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum FlushMode {
+    #[default]
+    Never,
+    Newline,
+    Always,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum BoundaryPolicy {
+    #[default]
+    Clamp,
+    Error,
+    Grow,
+    GrowBoth,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum CellFormat {
+    #[default]
+    Decimal,
+    Hex,
+    Ascii,
+}
+
+impl CellFormat {
+    /// Renders one cell; `Ascii` shows anything unprintable as `.`.
+    pub fn format(self, cell: u8) -> String {
+        match self {
+            CellFormat::Decimal => cell.to_string(),
+            CellFormat::Hex => format!("{:02x}", cell),
+            CellFormat::Ascii if cell.is_ascii_graphic() => {
+                char::from(cell).to_string()
+            }
+            CellFormat::Ascii => String::from("."),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum OutputMode {
+    #[default]
+    Raw,
+    Mask,
+    Escape,
+}
+
 #[derive(Debug)]
 pub enum Error {
     ReadError(io::Error),
     WriteError(io::Error),
     UnbalancedParens,
+    Interrupted,
+    Timeout,
+    TapeOverflow,
+    NestingTooDeep,
+    OutputLimitExceeded,
+    InstructionOutOfRange,
+    Io(io::Error),
+}
+
+impl Error {
+    pub fn from_read(err: io::Error) -> Error {
+        Error::ReadError(err)
+    }
+
+    pub fn from_write(err: io::Error) -> Error {
+        Error::WriteError(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        match (self, other) {
+            (Error::ReadError(a), Error::ReadError(b))
+            | (Error::WriteError(a), Error::WriteError(b))
+            | (Error::Io(a), Error::Io(b)) => a.kind() == b.kind(),
+            (Error::UnbalancedParens, Error::UnbalancedParens)
+            | (Error::Interrupted, Error::Interrupted)
+            | (Error::Timeout, Error::Timeout)
+            | (Error::TapeOverflow, Error::TapeOverflow)
+            | (Error::NestingTooDeep, Error::NestingTooDeep)
+            | (Error::OutputLimitExceeded, Error::OutputLimitExceeded)
+            | (Error::InstructionOutOfRange, Error::InstructionOutOfRange) => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::ReadError(err) => write!(f, "read error: {}", err),
+            Error::WriteError(err) => write!(f, "write error: {}", err),
+            Error::UnbalancedParens => write!(f, "unbalanced parentheses"),
+            Error::Interrupted => write!(f, "interrupted"),
+            Error::Timeout => write!(f, "timed out"),
+            Error::TapeOverflow => write!(f, "pointer moved off the tape"),
+            Error::NestingTooDeep => write!(f, "loops nested too deep"),
+            Error::OutputLimitExceeded => write!(f, "output limit exceeded"),
+            Error::InstructionOutOfRange => {
+                write!(f, "instruction pointer out of range")
+            }
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+/// Runs `program` against `input` and collects everything it prints.
+///
+/// ```
+/// let output = brainfuck::eval(",.", &[66]).unwrap();
+///
+/// assert_eq!(vec![66], output);
+/// ```
+pub fn eval(program: &str, mut input: &[u8]) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    Brainfuck::new(program).run(&mut input, &mut output)?;
+
+    Ok(output)
+}
+
+/// Runs `program` once per `(input, expected output)` pair, for test suites.
+///
+/// # Panics
+///
+/// Panics naming the first case whose run fails or prints something else.
+pub fn run_cases(program: &str, cases: &[(&[u8], &[u8])]) {
+    let compiled = compile(program);
+
+    for (case, &(mut input, expected)) in cases.iter().enumerate() {
+        let mut output = Vec::new();
+        let result = Brainfuck::from_compiled(compiled.clone())
+            .run(&mut input, &mut output);
+
+        if let Err(err) = result {
+            panic!("case {} failed: {:?}", case, err);
+        }
+
+        assert_eq!(expected, &output[..], "case {} printed wrong output", case);
+    }
+}
+
+/// Parses, optimizes and links `program` once, so that any number of machines
+/// can be started from it with `Brainfuck::from_compiled`.
+pub fn compile(program: &str) -> CompiledProgram {
+    let instructions =
+        Vec::from(optimizer::optimize(parser::parse(program.as_bytes())));
+
+    CompiledProgram {
+        jumps: optimizer::link(&instructions).into(),
+        instructions: instructions.into(),
+    }
 }
 
-impl Brainfuck {
-    pub fn new(program: &str) -> Brainfuck {
-        let instructions = parser::parse(program.as_bytes());
-        let optimized_instructions = optimizer::optimize(instructions);
+impl Brainfuck {
+    pub fn new(program: &str) -> Brainfuck {
+        Brainfuck::from_compiled(compile(program))
+    }
+
+    pub fn try_new(program: &str) -> std::result::Result<Brainfuck, ParseError> {
+        Ok(Brainfuck::from_parsed(parser::parse_checked(
+            program.as_bytes(),
+        )?))
+    }
+
+    pub fn from_ook(src: &str) -> Brainfuck {
+        Brainfuck::from_parsed(parser::parse_ook(src))
+    }
+
+    pub fn from_dialect(src: &str, dialect: &Dialect) -> Brainfuck {
+        Brainfuck::from_parsed(parser::parse_with_dialect(src, dialect))
+    }
+
+    pub fn from_words(src: &str, dialect: &WordDialect) -> Brainfuck {
+        Brainfuck::from_parsed(parser::parse_words(src, dialect))
+    }
+
+    /// Runs `program` with extra commands on top of the classic eight.
+    ///
+    /// Each registered character calls its handler with the machine when
+    /// executed. Only printable ASCII characters that aren't already commands
+    /// can be registered; anything else is ignored like a comment.
+    pub fn with_extension(
+        program: &str,
+        map: HashMap<char, ExtHandler>,
+    ) -> Brainfuck {
+        let extensions: HashMap<u8, ExtHandler> = map
+            .into_iter()
+            .filter(|(c, _)| c.is_ascii_graphic())
+            .map(|(c, handler)| (c as u8, handler))
+            .collect();
+        let chars: Vec<u8> = extensions.keys().copied().collect();
+
+        let mut brainfuck = Brainfuck::from_parsed(
+            parser::parse_with_extensions(program.as_bytes(), &chars),
+        );
+        brainfuck.extensions = extensions;
+        brainfuck
+    }
+
+    /// Runs `program` reading its input from `recorded` instead of the reader
+    /// passed to `run`, e.g. bytes captured with `recorded_input`.
+    pub fn replay(program: &str, recorded: &[u8]) -> Brainfuck {
+        let mut brainfuck = Brainfuck::new(program);
+        brainfuck.replaying = Some(recorded.iter().copied().collect());
+        brainfuck
+    }
+
+    pub fn from_instructions(instructions: Vec<Instruction>) -> Brainfuck {
+        Brainfuck::from_compiled(CompiledProgram {
+            jumps: optimizer::link(&instructions).into(),
+            instructions: instructions.into(),
+        })
+    }
+
+    /// Runs a program whose loops were nested with `optimizer::nest`.
+    pub fn from_nodes(nodes: &[Node]) -> Brainfuck {
+        Brainfuck::from_instructions(optimizer::flatten(nodes))
+    }
+
+    /// Starts a machine from `program`. Its instructions are shared, not
+    /// copied, so starting many short-lived machines from one program is cheap.
+    pub fn from_compiled(program: CompiledProgram) -> Brainfuck {
+        Brainfuck {
+            instructions: program.instructions,
+            jumps: program.jumps,
+            ip: 0,
+            printed: 0,
+            tape: vec![0; TAPE_SIZE],
+            dp: 0,
+            origin: 0,
+            high_water: 0,
+            steps: 0,
+            last_write: None,
+            stack: Vec::new(),
+            pointer_mode: PointerMode::default(),
+            cell_arith: CellArith::default(),
+            boundary_policy: BoundaryPolicy::default(),
+            output_mode: OutputMode::default(),
+            flush_mode: FlushMode::default(),
+            cell_format: CellFormat::default(),
+            opt_level: OptLevel::default(),
+            max_loop_depth: None,
+            stop_on_broken_pipe: false,
+            interrupt: None,
+            profile: None,
+            recording: None,
+            replaying: None,
+            supplied: None,
+            deadline: None,
+            extensions: HashMap::new(),
+            rng: seed_state(0),
+            name: None,
+            initial_dp: 0,
+        }
+    }
+
+    fn from_parsed(instructions: VecDeque<Instruction>) -> Brainfuck {
+        Brainfuck::from_instructions(Vec::from(optimizer::optimize(instructions)))
+    }
+
+    pub fn load(&mut self, program: &str) {
+        let instructions = parser::parse(program.as_bytes());
+
+        let instructions = Vec::from(optimizer::optimize_with(
+            instructions,
+            self.opt_level,
+            self.semantics(),
+        ));
+
+        self.jumps = optimizer::link(&instructions).into();
+        self.instructions = instructions.into();
+        self.ip = 0;
+        self.printed = 0;
+        self.stack.clear();
+
+        if let Some(counts) = self.profile.as_mut() {
+            *counts = vec![0; self.instructions.len()];
+        }
+    }
+
+    pub(crate) fn semantics(&self) -> Semantics {
+        Semantics {
+            cell_arith: self.cell_arith,
+            pointer_mode: self.pointer_mode,
+            boundary_policy: self.boundary_policy,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.tape = vec![0; TAPE_SIZE];
+        self.ip = 0;
+        self.printed = 0;
+        self.dp = self.initial_dp;
+        self.origin = 0;
+        self.high_water = self.initial_dp;
+        self.steps = 0;
+        self.stack.clear();
+
+        if let Some(counts) = self.profile.as_mut() {
+            counts.iter_mut().for_each(|count| *count = 0);
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Brainfuck> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        if reader.fill_buf()?.starts_with(b"#!") {
+            reader.read_until(b'\n', &mut Vec::new())?;
+        }
+
+        Ok(Brainfuck::from_parsed(parser::parse_reader(reader)?))
+    }
+
+    /// Like `from_file`, but parses straight from a memory map of the file.
+    #[cfg(feature = "memmap2")]
+    pub fn from_mmap<P: AsRef<Path>>(path: P) -> io::Result<Brainfuck> {
+        // Safe as long as nothing truncates the file while it's being parsed.
+        let map = unsafe { memmap2::Mmap::map(&File::open(path)?)? };
+        let mut source = &map[..];
+
+        if source.starts_with(b"#!") {
+            source = match source.iter().position(|&b| b == b'\n') {
+                Some(newline) => &source[newline + 1..],
+                None => &[],
+            };
+        }
+
+        Ok(Brainfuck::from_parsed(parser::parse(source)))
+    }
+
+    #[allow(dead_code)]
+    pub fn tape_pointer(&self) -> usize {
+        self.dp
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Position of the pointer relative to the starting cell, which goes
+    /// negative once a `GrowBoth` tape has grown to the left.
+    pub fn logical_pointer(&self) -> isize {
+        self.dp as isize - self.origin as isize
+    }
+
+    /// Index in `tape` of the cell the program started on.
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    pub fn set_tape_pointer(&mut self, dp: usize) -> Result {
+        if dp >= self.tape.len() {
+            return Err(Error::TapeOverflow);
+        }
+
+        self.dp = dp;
+        self.high_water = cmp::max(self.high_water, dp);
+
+        Ok(())
+    }
+
+    /// Moves the instruction pointer back (or forward) to `ip`, leaving the
+    /// tape alone. The loops around `ip` count as entered, so the program
+    /// carries on as if it had got there by itself. `ip` may be one past the
+    /// last instruction, which halts the program.
+    pub fn rewind_to(&mut self, ip: usize) -> Result {
+        if ip > self.instructions.len() {
+            return Err(Error::InstructionOutOfRange);
+        }
+
+        self.ip = ip;
+        self.printed = 0;
+        self.stack = (0..ip)
+            .filter(|&open| {
+                self.instructions[open] == Instruction::Open
+                    && self.jumps[open] >= ip
+            })
+            .collect();
+
+        Ok(())
+    }
+
+    pub fn recorded_input(&self) -> &[u8] {
+        self.recording.as_deref().unwrap_or(&[])
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.ip >= self.instructions.len()
+    }
+
+    pub fn instruction_count(&self) -> usize {
+        self.instructions.len()
+    }
+
+    pub fn current_cell(&self) -> u8 {
+        self.get_byte()
+    }
+
+    pub fn cell(&self, index: usize) -> Option<u8> {
+        self.tape.get(index).copied()
+    }
+
+    pub fn instruction_pointer(&self) -> usize {
+        self.ip
+    }
+
+    pub fn loop_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn profile(&self) -> HashMap<&'static str, u64> {
+        let mut profile = HashMap::new();
+
+        for (instruction, &count) in
+            self.instructions.iter().zip(self.instruction_counts())
+        {
+            if count > 0 {
+                let total = profile.entry(instruction.name()).or_insert(0u64);
+                *total = total.saturating_add(count);
+            }
+        }
+
+        profile
+    }
+
+    /// How many times each loop body ran, keyed by the index of its `Open`.
+    /// Every pass through a body ends at its `Close`, so that's what is
+    /// counted. Empty unless profiling is enabled.
+    pub fn loop_iterations(&self) -> HashMap<usize, u64> {
+        let counts = self.instruction_counts();
+
+        counts
+            .iter()
+            .enumerate()
+            .filter(|&(ip, &count)| {
+                count > 0 && self.instructions[ip] == Instruction::Open
+            })
+            .map(|(ip, _)| {
+                let close = self.jumps[ip];
+                (ip, counts.get(close).copied().unwrap_or(0))
+            })
+            .collect()
+    }
+
+    pub fn instruction_counts(&self) -> &[u64] {
+        match self.profile {
+            Some(ref counts) => counts,
+            None => &[],
+        }
+    }
+
+    pub fn tape_len(&self) -> usize {
+        self.tape.len()
+    }
+
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water
+    }
+
+    /// Number of instructions executed since the last reset, saturating at
+    /// `u64::MAX`.
+    pub fn step_count(&self) -> u64 {
+        self.steps
+    }
+
+    #[allow(dead_code)]
+    pub fn tape(&self, range: Range<usize>) -> &[u8] {
+        &self.tape[range]
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Formats `err`, prefixed with the program's name if it has one.
+    pub fn describe(&self, err: &Error) -> String {
+        match self.name {
+            Some(ref name) => format!("program '{}': {}", name, err),
+            None => err.to_string(),
+        }
+    }
+
+    pub fn cell_format(&self) -> CellFormat {
+        self.cell_format
+    }
+
+    pub fn set_cell_format(&mut self, format: CellFormat) {
+        self.cell_format = format;
+    }
+
+    /// The cells in `range` rendered with `cell_format`, separated by spaces.
+    pub fn render_tape(&self, range: Range<usize>) -> String {
+        self.tape(range)
+            .iter()
+            .map(|&cell| self.cell_format.format(cell))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Cells within `radius` of `center`, cut short at either end of the tape.
+    pub fn tape_window(&self, center: usize, radius: usize) -> &[u8] {
+        &self.tape[self.window(center, radius)]
+    }
+
+    fn window(&self, center: usize, radius: usize) -> Range<usize> {
+        let end = cmp::min(
+            center.saturating_add(radius).saturating_add(1),
+            self.tape.len(),
+        );
+        let start = cmp::min(center.saturating_sub(radius), end);
+
+        start..end
+    }
+
+    /// Copies `bytes` onto the tape starting at cell `offset`.
+    ///
+    /// Nothing is written and `Error::TapeOverflow` is returned when the bytes
+    /// would not fit on the tape.
+    pub fn preload(&mut self, offset: usize, bytes: &[u8]) -> Result {
+        let cells = offset
+            .checked_add(bytes.len())
+            .and_then(|end| self.tape.get_mut(offset..end))
+            .ok_or(Error::TapeOverflow)?;
+
+        cells.copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    /// Fills the whole tape with bytes from a xorshift generator started at
+    /// `seed`, so the same seed always gives the same tape.
+    pub fn seed_tape(&mut self, seed: u64) {
+        let mut state = seed_state(seed);
+
+        for chunk in self.tape.chunks_mut(8) {
+            let bytes = xorshift(&mut state).to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    /// Restarts the generator behind `random_byte` from `seed`.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = seed_state(seed);
+    }
+
+    /// The next byte from the machine's own xorshift generator, for extensions
+    /// that need randomness but must stay reproducible under `seed_rng`.
+    pub fn random_byte(&mut self) -> u8 {
+        xorshift(&mut self.rng) as u8
+    }
+
+    #[allow(dead_code)]
+    pub fn run_pure(&mut self) -> Result {
+        self.run(&mut io::empty(), &mut io::sink())
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            tape: self.tape.clone(),
+            ip: self.ip,
+            dp: self.dp,
+            origin: self.origin,
+            stack: self.stack.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.tape.clone_from(&snapshot.tape);
+        self.ip = snapshot.ip;
+        self.printed = 0;
+        self.dp = snapshot.dp;
+        self.origin = snapshot.origin;
+        self.stack.clone_from(&snapshot.stack);
+    }
+
+    pub fn output_iter<R: io::Read>(self, input: R) -> OutputIter<R> {
+        OutputIter {
+            brainfuck: self,
+            input,
+            pending: VecDeque::new(),
+            failed: false,
+        }
+    }
+
+    pub fn run<R, W>(&mut self, input: &mut R, output: &mut W) -> Result
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        while self.current().is_some() {
+            self.check_interrupt()?;
+
+            match self.step(input, output) {
+                Err(Error::WriteError(ref e))
+                    if self.stop_on_broken_pipe
+                        && e.kind() == io::ErrorKind::BrokenPipe =>
+                {
+                    return Ok(());
+                }
+                result => {
+                    result?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs like `run`, collecting the output in a vector that starts out with
+    /// room for `capacity` bytes.
+    pub fn run_to_vec_with_capacity<R: io::Read>(
+        &mut self,
+        input: &mut R,
+        capacity: usize,
+    ) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(capacity);
+        self.run(input, &mut output)?;
+
+        Ok(output)
+    }
+
+    /// Reads all of `input` up front, runs against it and returns whatever the
+    /// program left unread, ready to be fed to the next program.
+    pub fn run_consuming<R, W>(
+        &mut self,
+        mut input: R,
+        output: &mut W,
+    ) -> Result<Vec<u8>>
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        let mut buffered = Vec::new();
+        input.read_to_end(&mut buffered).map_err(Error::ReadError)?;
+
+        let mut remaining = &buffered[..];
+        self.run(&mut remaining, output)?;
+
+        Ok(remaining.to_vec())
+    }
+
+    /// Runs like `run`, writing everything the program prints to both
+    /// `primary` and `secondary`. A failure on either is a `WriteError`.
+    pub fn run_tee<R, W, T>(
+        &mut self,
+        input: &mut R,
+        primary: &mut W,
+        secondary: &mut T,
+    ) -> Result
+    where
+        R: io::Read,
+        W: io::Write,
+        T: io::Write,
+    {
+        self.run(input, &mut Tee(primary, secondary))
+    }
+
+    /// Runs like `run`, but fails with `OutputLimitExceeded` as soon as the
+    /// program tries to print more than `max_bytes`. Everything up to the limit
+    /// still reaches `output`.
+    pub fn run_with_output_limit<R, W>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+        max_bytes: u64,
+    ) -> Result
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        let mut limited = Limited {
+            inner: output,
+            remaining: max_bytes,
+            exceeded: false,
+        };
+
+        while self.current().is_some() {
+            self.check_interrupt()?;
+
+            match self.step(input, &mut limited) {
+                Err(_) if limited.exceeded => {
+                    return Err(Error::OutputLimitExceeded);
+                }
+                result => {
+                    result?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs until the program prints `sentinel`, returning `true`, or until it
+    /// ends, returning `false`. Running again continues after the sentinel,
+    /// even when it stopped partway through consecutive prints of one cell.
+    pub fn run_until_byte<R, W>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+        sentinel: u8,
+    ) -> Result<bool>
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        while let Some(&instruction) = self.current() {
+            self.check_interrupt()?;
+
+            let printing = match instruction {
+                Instruction::Out | Instruction::OutN(_) => {
+                    self.get_byte() == sentinel
+                }
+                _ => false,
+            };
+
+            match instruction {
+                Instruction::OutN(n) if printing && n - self.printed > 1 => {
+                    let (buffer, len) = self.render_byte();
+                    self.emit(output, &buffer[..len])?;
+                    self.printed += 1;
+                }
+                _ => {
+                    self.step(input, output)?;
+                }
+            }
+
+            if printing {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Runs like `run`, except that a `,` finding `input` exhausted suspends
+    /// the machine instead of reading end of input. Calling `resume` again
+    /// with more input continues from that `,`.
+    pub fn resume<R, W>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<RunState>
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        while let Some(&instruction) = self.current() {
+            self.check_interrupt()?;
+
+            if instruction == Instruction::In && self.replaying.is_none() {
+                match self.read_byte(input)? {
+                    Some(byte) => self.step(&mut &[byte][..], output)?,
+                    None => return Ok(RunState::Suspended),
+                };
+            } else {
+                self.step(input, output)?;
+            }
+        }
+
+        Ok(RunState::Halted)
+    }
+
+    /// Runs until the program reaches a `,` with no byte given through
+    /// `supply_input`, returning `RunState::Suspended`, or until it ends.
+    pub fn run_until_input<W>(&mut self, output: &mut W) -> Result<RunState>
+    where
+        W: io::Write,
+    {
+        while let Some(&instruction) = self.current() {
+            self.check_interrupt()?;
+
+            if instruction == Instruction::In && self.replaying.is_none() {
+                match self.supplied.take() {
+                    Some(byte) => self.step(&mut &[byte][..], output)?,
+                    None => return Ok(RunState::Suspended),
+                };
+            } else {
+                self.step(&mut io::empty(), output)?;
+            }
+        }
+
+        Ok(RunState::Halted)
+    }
+
+    /// Queues the byte read by the next `,` in `run_until_input`.
+    pub fn supply_input(&mut self, byte: u8) {
+        self.supplied = Some(byte);
+    }
+
+    pub fn run_with_sink<R, F>(&mut self, input: &mut R, on_out: F) -> Result
+    where
+        R: io::Read,
+        F: FnMut(u8),
+    {
+        self.run(input, &mut Sink(on_out))
+    }
+
+    pub fn run_with_timeout<R, W>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+        timeout: Duration,
+    ) -> Result
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        self.deadline = Instant::now().checked_add(timeout);
+        let result = self.run_until_deadline(input, output);
+        self.deadline = None;
+
+        result
+    }
+
+    fn run_until_deadline<R, W>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        let mut steps: u64 = 0;
+
+        while self.current().is_some() {
+            if steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL) {
+                self.check_deadline()?;
+            }
+
+            steps = steps.wrapping_add(1);
+            self.check_interrupt()?;
+            self.step(input, output)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn run_traced<R, W, T>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+        trace: &mut T,
+    ) -> Result
+    where
+        R: io::Read,
+        W: io::Write,
+        T: io::Write,
+    {
+        while let Some(&instruction) = self.current() {
+            let ip = self.ip;
+            self.check_interrupt()?;
+            self.step(input, output)?;
+
+            let arg = match instruction {
+                Instruction::Add(n)
+                | Instruction::Sub(n)
+                | Instruction::Set(n) => n.to_string(),
+                Instruction::Right(n)
+                | Instruction::Left(n)
+                | Instruction::OutN(n) => n.to_string(),
+                Instruction::Ext(c) => c.to_string(),
+                Instruction::Move { offset } => offset.to_string(),
+                Instruction::AddOffset { n, .. }
+                | Instruction::SubOffset { n, .. } => n.to_string(),
+                _ => String::from("null"),
+            };
+
+            writeln!(
+                trace,
+                "{{\"ip\": {}, \"dp\": {}, \"instr\": \"{}\", \"arg\": {}, \"cell\": {}}}",
+                ip,
+                self.dp,
+                instruction.name(),
+                arg,
+                self.get_byte()
+            )
+            .map_err(Error::WriteError)?;
+        }
+
+        Ok(())
+    }
+
+    /// Like `step`, except that on an `Open` the whole loop runs, stopping
+    /// just past its matching `Close`.
+    pub fn step_over_loop<R, W>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        if self.current() != Some(&Instruction::Open) {
+            return self.step(input, output).map(|_| ());
+        }
+
+        let close = self.jumps[self.ip];
+
+        while self.current().is_some() && self.ip <= close {
+            self.check_interrupt()?;
+            self.step(input, output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes one instruction, returning the index and new value of the cell
+    /// it wrote, if any.
+    pub fn step<R, W>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<Option<(usize, u8)>>
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        self.last_write = None;
+
+        if let Some(counts) = self.profile.as_mut() {
+            if let Some(count) = counts.get_mut(self.ip) {
+                bump(count);
+            }
+        }
+
+        match self.current() {
+            Some(&Instruction::Right(n)) => self.move_right(n)?,
+            Some(&Instruction::Left(n)) => self.move_left(n)?,
+            Some(&Instruction::Move { offset }) => self.transfer(offset)?,
+            Some(&Instruction::AddOffset { offset, n }) => {
+                let arith = self.cell_arith;
+                self.at_offset(offset, |cell| match arith {
+                    CellArith::Wrap => cell.wrapping_add(n),
+                    CellArith::Saturate => cell.saturating_add(n),
+                })?;
+            }
+            Some(&Instruction::SubOffset { offset, n }) => {
+                let arith = self.cell_arith;
+                self.at_offset(offset, |cell| match arith {
+                    CellArith::Wrap => cell.wrapping_sub(n),
+                    CellArith::Saturate => cell.saturating_sub(n),
+                })?;
+            }
+            Some(&Instruction::Clear) => self.set_byte(0),
+            Some(&Instruction::Set(n)) => self.set_byte(n),
+            Some(&Instruction::Swap) => {
+                // Reach the right neighbour the way `>` would, so the boundary
+                // policy decides what happens at the end of the tape.
+                let origin = self.dp;
+                let left = self.get_byte();
+                self.move_right(1)?;
+                let right = self.get_byte();
+
+                self.set_byte(left);
+                self.dp = origin;
+                self.set_byte(right);
+            }
+            Some(&Instruction::Add(n)) => self.add_byte(n),
+            Some(&Instruction::Sub(n)) => self.sub_byte(n),
+            Some(&Instruction::Out) => {
+                let (buffer, len) = self.render_byte();
+                self.emit(output, &buffer[..len])?;
+            }
+            Some(&Instruction::OutN(n)) => {
+                let (buffer, len) = self.render_byte();
+                // `run_until_byte` may have printed some of these already.
+                let left = n - mem::take(&mut self.printed);
+                self.emit(output, &buffer[..len].repeat(left))?;
+            }
+            Some(&Instruction::OutDecimal) => {
+                self.emit(output, self.get_byte().to_string().as_bytes())?;
+            }
+            Some(&Instruction::OutUnicode) => {
+                let high = self.tape.get(self.dp + 1).copied().unwrap_or(0);
+                let code = u16::from_le_bytes([self.get_byte(), high]);
+                let c = char::from_u32(u32::from(code))
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+
+                self.emit(output, c.encode_utf8(&mut [0; 4]).as_bytes())?;
+            }
+            Some(&Instruction::In) => {
+                let byte = match self.replaying.as_mut() {
+                    Some(recorded) => recorded.pop_front(),
+                    None => self.read_byte(input)?,
+                };
+
+                if let (Some(byte), Some(recording)) =
+                    (byte, self.recording.as_mut())
+                {
+                    recording.push(byte);
+                }
+
+                self.set_byte(byte.unwrap_or(0));
+            }
+            Some(&Instruction::Ext(c)) => {
+                if let Some(handler) = self.extensions.get(&c).cloned() {
+                    handler(self);
+                }
+            }
+            Some(&Instruction::Open) => {
+                if self.get_byte() == 0 {
+                    self.advance_to_matching_paren()?;
+                } else {
+                    self.push()?;
+                }
+            }
+            Some(&Instruction::Close) => {
+                if self.get_byte() != 0 {
+                    self.return_to_matching_paren()?;
+                } else {
+                    self.pop();
+                }
+            }
+            None => {
+                return Ok(None);
+            }
+        };
+
+        bump(&mut self.steps);
+        self.advance();
+
+        Ok(self.last_write)
+    }
+
+    /// Reads a single byte, retrying reads that were interrupted or had nothing
+    /// ready yet. While it waits, an interrupt or the deadline of
+    /// `run_with_timeout` still stops the machine.
+    fn read_byte<R: io::Read>(&self, input: &mut R) -> Result<Option<u8>> {
+        let mut buffer = [0; 1];
+
+        loop {
+            match input.read(&mut buffer) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(buffer[0])),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.check_interrupt()?;
+                    self.check_deadline()?;
+                    thread::yield_now();
+                }
+                Err(e) => return Err(Error::ReadError(e)),
+            }
+        }
+    }
+
+    fn check_deadline(&self) -> Result {
+        match self.deadline {
+            Some(deadline) if Instant::now() > deadline => Err(Error::Timeout),
+            _ => Ok(()),
+        }
+    }
+
+    #[inline(always)]
+    fn check_interrupt(&self) -> Result {
+        match self.interrupt {
+            Some(ref flag) if flag.load(Ordering::Relaxed) => {
+                Err(Error::Interrupted)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[inline(always)]
+    fn move_right(&mut self, n: usize) -> Result {
+        let target = self.dp.checked_add(n).filter(|&dp| dp < self.tape.len());
+
+        match self.pointer_mode {
+            PointerMode::Clamp if target.is_some() => {
+                self.dp += n;
+            }
+            PointerMode::Clamp => match self.boundary_policy {
+                BoundaryPolicy::Clamp => {
+                    self.dp = self.tape.len() - 1;
+                }
+                BoundaryPolicy::Error => {
+                    return Err(Error::TapeOverflow);
+                }
+                BoundaryPolicy::Grow | BoundaryPolicy::GrowBoth => {
+                    let dp = self.dp.checked_add(n).ok_or(Error::TapeOverflow)?;
+                    let len = cmp::max(dp.saturating_add(1), self.tape.len() * 2);
+
+                    self.grow(len - self.tape.len())?;
+                    self.tape.resize(len, 0);
+                    self.dp = dp;
+                }
+            },
+            PointerMode::Wrap => {
+                self.dp = (self.dp + n % self.tape.len()) % self.tape.len();
+            }
+        }
+
+        self.high_water = cmp::max(self.high_water, self.dp);
+
+        Ok(())
+    }
+
+    /// Makes room for `extra` more cells, failing instead of aborting when a
+    /// huge move asks for more than can be allocated.
+    fn grow(&mut self, extra: usize) -> Result {
+        self.tape
+            .try_reserve_exact(extra)
+            .map_err(|_| Error::TapeOverflow)
+    }
+
+    /// Moves the pointer `offset` cells, or back by as many with `back`.
+    fn shift(&mut self, offset: isize, back: bool) -> Result {
+        if (offset < 0) != back {
+            self.move_left(offset.unsigned_abs())
+        } else {
+            self.move_right(offset.unsigned_abs())
+        }
+    }
+
+    /// Empties the current cell into the one `offset` away, as `[->+<]` does.
+    /// Where that loop would run into an end of the tape, it is run step by
+    /// step instead, so the pointer ends up wherever the loop leaves it.
+    fn transfer(&mut self, offset: isize) -> Result {
+        let value = self.get_byte();
+
+        if value == 0 {
+            return Ok(());
+        }
+
+        let target = if offset < 0 {
+            self.dp.checked_sub(offset.unsigned_abs())
+        } else {
+            self.dp.checked_add(offset.unsigned_abs())
+        };
+        let on_tape = target.is_some_and(|target| target < self.tape.len());
+
+        if !on_tape && self.pointer_mode == PointerMode::Clamp {
+            while self.get_byte() != 0 {
+                self.check_interrupt()?;
+                self.sub_byte(1);
+                self.shift(offset, false)?;
+                self.add_byte(1);
+                self.shift(offset, true)?;
+            }
+
+            return Ok(());
+        }
+
+        let origin = self.dp;
+        self.set_byte(0);
+        self.shift(offset, false)?;
+
+        let byte = match self.cell_arith {
+            CellArith::Wrap => self.get_byte().wrapping_add(value),
+            CellArith::Saturate => self.get_byte().saturating_add(value),
+        };
+        self.set_byte(byte);
+        self.dp = origin;
+
+        Ok(())
+    }
+
+    /// Rewrites the cell `offset` away, reached the way `>` or `<` would, then
+    /// puts the pointer back where it was.
+    fn at_offset<F: FnOnce(u8) -> u8>(&mut self, offset: isize, f: F) -> Result {
+        let dp = self.dp;
+        let origin = self.origin;
+
+        self.shift(offset, false)?;
+
+        let byte = f(self.get_byte());
+        self.set_byte(byte);
+        // Growing at the front shifts every cell, including the one we left.
+        self.dp = dp + (self.origin - origin);
+
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn move_left(&mut self, n: usize) -> Result {
+        match self.pointer_mode {
+            PointerMode::Clamp
+                if n > self.dp
+                    && self.boundary_policy == BoundaryPolicy::GrowBoth =>
+            {
+                let missing = n - self.dp;
+                let extra = cmp::max(missing, self.tape.len());
+
+                self.grow(extra)?;
+                self.tape.splice(0..0, std::iter::repeat_n(0, extra));
+                self.origin += extra;
+                self.high_water += extra;
+                self.dp = extra - missing;
+            }
+            PointerMode::Clamp => {
+                self.dp = self.dp.saturating_sub(n);
+            }
+            PointerMode::Wrap => {
+                let n = n % self.tape.len();
+                self.dp = (self.dp + self.tape.len() - n) % self.tape.len();
+            }
+        }
+
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn render_byte(&self) -> ([u8; 4], usize) {
+        let byte = self.get_byte();
+
+        match self.output_mode {
+            OutputMode::Raw => ([byte, 0, 0, 0], 1),
+            OutputMode::Mask => ([byte & 0x7f, 0, 0, 0], 1),
+            OutputMode::Escape
+                if byte.is_ascii_graphic() || byte == b' ' || byte == b'\n' =>
+            {
+                ([byte, 0, 0, 0], 1)
+            }
+            OutputMode::Escape => {
+                const HEX: &[u8; 16] = b"0123456789abcdef";
+                let high = HEX[usize::from(byte >> 4)];
+                let low = HEX[usize::from(byte & 0xf)];
+
+                ([b'\\', b'x', high, low], 4)
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn add_byte(&mut self, n: u8) {
+        let byte = match self.cell_arith {
+            CellArith::Wrap => self.get_byte().wrapping_add(n),
+            CellArith::Saturate => self.get_byte().saturating_add(n),
+        };
+        self.set_byte(byte);
+    }
+
+    #[inline(always)]
+    fn sub_byte(&mut self, n: u8) {
+        let byte = match self.cell_arith {
+            CellArith::Wrap => self.get_byte().wrapping_sub(n),
+            CellArith::Saturate => self.get_byte().saturating_sub(n),
+        };
+        self.set_byte(byte);
+    }
+
+    #[inline(always)]
+    fn set_byte(&mut self, byte: u8) {
+        let index = self.cell_index();
+        self.tape[index] = byte;
+        self.last_write = Some((index, byte));
+    }
+
+    #[inline(always)]
+    fn get_byte(&self) -> u8 {
+        self.tape[self.cell_index()]
+    }
+
+    /// Index of the cell under the pointer. A pointer past the end is a bug:
+    /// debug builds panic, release builds use the last cell.
+    #[inline(always)]
+    fn cell_index(&self) -> usize {
+        debug_assert!(
+            self.dp < self.tape.len(),
+            "data pointer {} is past the end of the tape ({} cells)",
+            self.dp,
+            self.tape.len()
+        );
+
+        cmp::min(self.dp, self.tape.len() - 1)
+    }
+
+    #[cfg(test)]
+    fn force_tape_pointer(&mut self, dp: usize) {
+        self.dp = dp;
+    }
+
+    #[inline(always)]
+    fn advance(&mut self) {
+        self.ip += 1;
+    }
+
+    #[inline(always)]
+    fn current(&self) -> Option<&Instruction> {
+        self.instructions.get(self.ip)
+    }
+
+    #[inline(always)]
+    fn pop(&mut self) {
+        let _ = self.stack.pop();
+    }
+
+    fn emit<W: io::Write>(&self, output: &mut W, bytes: &[u8]) -> Result {
+        write_bytes(output, bytes)?;
+
+        let flush = match self.flush_mode {
+            FlushMode::Never => false,
+            FlushMode::Newline => bytes.contains(&b'\n'),
+            FlushMode::Always => true,
+        };
+
+        if flush {
+            output.flush().map_err(Error::WriteError)?;
+        }
+
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn push(&mut self) -> Result {
+        match self.max_loop_depth {
+            Some(max) if self.stack.len() >= max => Err(Error::NestingTooDeep),
+            _ => {
+                self.stack.push(self.ip);
+                Ok(())
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn advance_to_matching_paren(&mut self) -> Result {
+        match self.jumps.get(self.ip) {
+            Some(&ip) if ip < self.instructions.len() => {
+                self.ip = ip;
+            }
+            _ => return Err(Error::UnbalancedParens),
+        }
+
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn return_to_matching_paren(&mut self) -> Result {
+        match self.stack.last() {
+            Some(ip) => {
+                self.ip = *ip;
+            }
+            None => return Err(Error::UnbalancedParens),
+        }
+
+        Ok(())
+    }
+}
+
+struct Sink<F>(F);
+
+impl<F: FnMut(u8)> io::Write for Sink<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            (self.0)(byte);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Tee<'a, W, T>(&'a mut W, &'a mut T);
+
+impl<'a, W: io::Write, T: io::Write> io::Write for Tee<'a, W, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+struct Limited<'a, W> {
+    inner: &'a mut W,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<'a, W: io::Write> io::Write for Limited<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !buf.is_empty() {
+            self.exceeded = true;
+            return Err(io::Error::other("output limit exceeded"));
+        }
+
+        let len = self.remaining.min(buf.len() as u64) as usize;
+        let written = self.inner.write(&buf[..len])?;
+        self.remaining -= written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: io::Read> Iterator for OutputIter<R> {
+    type Item = Result<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            if self.failed || self.brainfuck.current().is_none() {
+                return None;
+            }
+
+            let step = self.brainfuck.step(&mut self.input, &mut self.pending);
+
+            if let Err(err) = step {
+                self.failed = true;
+                return Some(Err(err));
+            }
+        }
+
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+/// Writes all of `bytes`, retrying interrupted writes and writes that accepted
+/// nothing. Gives up with `WriteZero` after `MAX_ZERO_WRITES` empty writes in
+/// a row.
+fn write_bytes<W: io::Write>(output: &mut W, mut bytes: &[u8]) -> Result {
+    let mut zero_writes = 0;
+
+    while !bytes.is_empty() {
+        match output.write(bytes) {
+            Ok(0) if zero_writes < MAX_ZERO_WRITES => zero_writes += 1,
+            Ok(0) => {
+                return Err(Error::WriteError(io::ErrorKind::WriteZero.into()))
+            }
+            Ok(written) => {
+                bytes = &bytes[written..];
+                zero_writes = 0;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::WriteError(e)),
+        }
+    }
+
+    Ok(())
+}
+
+fn seed_state(seed: u64) -> u64 {
+    match seed ^ SEED_MIX {
+        0 => SEED_MIX,
+        state => state,
+    }
+}
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+/// Counts one more event, sticking at `u64::MAX` instead of overflowing.
+#[inline(always)]
+fn bump(counter: &mut u64) {
+    *counter = counter.saturating_add(1);
+}
+
+impl fmt::Debug for Brainfuck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let window = self.window(self.dp, DEBUG_TAPE_RADIUS);
+
+        f.debug_struct("Brainfuck")
+            .field("ip", &self.ip)
+            .field("dp", &self.dp)
+            .field("instructions", &self.instruction_count())
+            .field("tape_offset", &window.start)
+            .field("tape", &&self.tape[window])
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        compile, BoundaryPolicy, Brainfuck, CellArith, CellFormat, Dialect,
+        Error, ExtHandler, FlushMode, Instruction, OptLevel, OutputMode,
+        ParseError, PointerMode, RunState, WordDialect,
+    };
+    use crate::{builder::BrainfuckBuilder, optimizer};
+    use std::{
+        cmp,
+        collections::HashMap,
+        env, fs, io,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn initialized() {
+        let brainfuck = Brainfuck::new("");
+
+        assert_eq!(0, brainfuck.tape_pointer());
+        assert_eq!(&[0, 0, 0, 0], brainfuck.tape(0..4));
+    }
+
+    #[test]
+    fn instruction_greater_than() {
+        let mut brainfuck = Brainfuck::new(">");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(1, brainfuck.tape_pointer());
+    }
+
+    #[test]
+    fn high_water_mark() {
+        let mut brainfuck = Brainfuck::new(">>>><<<>");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(2, brainfuck.tape_pointer());
+        assert_eq!(4, brainfuck.high_water_mark());
+    }
+
+    #[test]
+    fn unbalanced_open_after_dead_loop() {
+        let mut brainfuck = Brainfuck::new("[-][+.>");
+
+        assert_eq!(Err(Error::UnbalancedParens), brainfuck.run_pure());
+    }
+
+    #[test]
+    fn unbalanced_open_instructions() {
+        let mut brainfuck = Brainfuck::from_instructions(vec![
+            Instruction::Open,
+            Instruction::Add(1),
+        ]);
+
+        assert_eq!(Err(Error::UnbalancedParens), brainfuck.run_pure());
+        assert_eq!(0, brainfuck.instruction_pointer());
+    }
+
+    #[test]
+    fn preload() {
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::new(".>.");
+        brainfuck.preload(0, b"Hi").unwrap();
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!(b"Hi".to_vec(), output);
+    }
+
+    #[test]
+    fn preload_out_of_range() {
+        let mut brainfuck = Brainfuck::new("");
+
+        assert!(matches!(
+            brainfuck.preload(29_999, b"Hi"),
+            Err(Error::TapeOverflow)
+        ));
+        assert!(matches!(
+            brainfuck.preload(usize::MAX, b"Hi"),
+            Err(Error::TapeOverflow)
+        ));
+        assert_eq!(&[0], brainfuck.tape(29_999..30_000));
+    }
+
+    #[test]
+    fn extension() {
+        let double: ExtHandler = Arc::new(|brainfuck: &mut Brainfuck| {
+            let dp = brainfuck.tape_pointer();
+            let cell = brainfuck.current_cell();
+            brainfuck.preload(dp, &[cell.wrapping_mul(2)]).unwrap();
+        });
+        let mut extensions = HashMap::new();
+        extensions.insert('@', double);
+
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::with_extension("+++@.@@.", extensions);
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!(vec!(6, 24), output);
+    }
+
+    #[test]
+    fn seeded_random_extension() {
+        let random: ExtHandler = Arc::new(|brainfuck: &mut Brainfuck| {
+            let dp = brainfuck.tape_pointer();
+            let byte = brainfuck.random_byte();
+            brainfuck.preload(dp, &[byte]).unwrap();
+        });
+        let run = |seed| {
+            let mut extensions = HashMap::new();
+            extensions.insert('?', random.clone());
+
+            let mut output = Vec::new();
+            let mut brainfuck = Brainfuck::with_extension("?.?.?.?.", extensions);
+            brainfuck.seed_rng(seed);
+            brainfuck.run(&mut io::empty(), &mut output).unwrap();
+            output
+        };
+
+        assert_eq!(run(42), run(42));
+        assert_ne!(run(42), run(43));
+
+        let mut a = BrainfuckBuilder::new("").rng_seed(42).build().unwrap();
+        let mut b = Brainfuck::new("");
+        b.seed_rng(42);
+
+        for _ in 0..8 {
+            assert_eq!(a.random_byte(), b.random_byte());
+        }
+    }
+
+    #[test]
+    fn whitespace_extension() {
+        let handler: ExtHandler = Arc::new(|brainfuck| {
+            let _ = brainfuck.random_byte();
+        });
+        let mut map = HashMap::new();
+        map.insert(' ', handler);
+
+        let brainfuck = Brainfuck::with_extension("+ .", map);
+        let text = crate::instruction::disassemble(brainfuck.instructions());
+
+        assert_eq!(
+            &[Instruction::Add(1), Instruction::Out],
+            brainfuck.instructions()
+        );
+        assert_eq!(
+            Ok(brainfuck.instructions().to_vec()),
+            crate::instruction::assemble(&text)
+        );
+    }
+
+    #[test]
+    fn unregistered_extension() {
+        let mut brainfuck =
+            Brainfuck::from_instructions(vec![Instruction::Ext(b'@')]);
+
+        assert!(brainfuck.run_pure().is_ok());
+    }
+
+    #[test]
+    fn saturating_counters() {
+        let mut counter = u64::MAX - 1;
+        super::bump(&mut counter);
+        super::bump(&mut counter);
+
+        assert_eq!(u64::MAX, counter);
+
+        let brainfuck = Brainfuck::new("");
+        let steps: u64 = brainfuck.step_count();
+        let counts: &[u64] = brainfuck.instruction_counts();
+
+        assert_eq!(0, steps);
+        assert!(counts.is_empty());
+    }
+
+    #[test]
+    fn error_equality() {
+        let broken = || io::Error::from(io::ErrorKind::BrokenPipe);
+
+        assert_eq!(Error::Timeout, Error::Timeout);
+        assert_ne!(Error::Timeout, Error::Interrupted);
+        assert_eq!(Error::WriteError(broken()), Error::WriteError(broken()));
+        assert_ne!(Error::ReadError(broken()), Error::WriteError(broken()));
+        assert_ne!(
+            Error::ReadError(broken()),
+            Error::ReadError(io::Error::from(io::ErrorKind::UnexpectedEof))
+        );
+    }
+
+    #[test]
+    fn seed_tape() {
+        let mut a = Brainfuck::new("");
+        let mut b = Brainfuck::new("");
+        a.seed_tape(42);
+        b.seed_tape(42);
+
+        assert_eq!(a.tape(0..16), b.tape(0..16));
+        assert!(a.tape(0..16).iter().any(|&cell| cell != 0));
+
+        b.seed_tape(43);
+
+        assert_ne!(a.tape(0..16), b.tape(0..16));
+
+        a.seed_tape(super::SEED_MIX);
+
+        assert!(a.tape(0..16).iter().any(|&cell| cell != 0));
+    }
+
+    #[test]
+    fn run_until_byte() {
+        let program = "+".repeat(65) + ".+.[-]." + &"+".repeat(67) + ".";
+        let mut brainfuck = Brainfuck::new(&program);
+        let mut output = Vec::new();
+
+        assert_eq!(
+            Ok(true),
+            brainfuck.run_until_byte(&mut io::empty(), &mut output, 0)
+        );
+        assert_eq!(b"AB\0".to_vec(), output);
+
+        output.clear();
+
+        assert_eq!(
+            Ok(false),
+            brainfuck.run_until_byte(&mut io::empty(), &mut output, 0)
+        );
+        assert_eq!(b"C".to_vec(), output);
+
+        let mut brainfuck = Brainfuck::new(".+..-...");
+        let mut output = Vec::new();
+        let mut frames = Vec::new();
+
+        while brainfuck
+            .run_until_byte(&mut io::empty(), &mut output, 0)
+            .unwrap()
+        {
+            frames.push(std::mem::take(&mut output));
+        }
+
+        assert_eq!(vec!(vec!(0), vec!(1, 1, 0), vec!(0), vec!(0)), frames);
+        assert!(output.is_empty());
+        assert_eq!(5, brainfuck.step_count());
+    }
+
+    #[test]
+    fn move_loops() {
+        for program in &[">+++++[-<+>]", "+++[->>+<<]>>", ">>++[<<+>>-]<+"] {
+            let mut plain = BrainfuckBuilder::new(program)
+                .opt_level(OptLevel::None)
+                .build()
+                .unwrap();
+            let mut optimized = Brainfuck::new(program);
+            plain.run_pure().unwrap();
+            optimized.run_pure().unwrap();
+
+            assert_eq!(plain.tape(0..4), optimized.tape(0..4));
+            assert_eq!(plain.tape_pointer(), optimized.tape_pointer());
+        }
+    }
+
+    #[test]
+    fn move_wraps_target_cell() {
+        let program = "+".repeat(200) + ">" + &"+".repeat(100) + "<[->+<]";
+        let mut brainfuck = Brainfuck::new(&program);
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[0, 44], brainfuck.tape(0..2));
+    }
+
+    #[test]
+    fn max_loop_depth() {
+        let program = "+".to_string() + &"[".repeat(1000) + &"]".repeat(1000);
+        let mut brainfuck = BrainfuckBuilder::new(&program)
+            .max_loop_depth(256)
+            .build()
+            .unwrap();
+
+        assert_eq!(Err(Error::NestingTooDeep), brainfuck.run_pure());
+        assert_eq!(256, brainfuck.loop_depth());
+    }
+
+    #[test]
+    fn within_max_loop_depth() {
+        let program = "[".repeat(1000) + &"]".repeat(1000) + "+[[-]]";
+        let mut brainfuck = BrainfuckBuilder::new(&program)
+            .max_loop_depth(2)
+            .build()
+            .unwrap();
+
+        assert_eq!(Ok(()), brainfuck.run_pure());
+    }
+
+    #[test]
+    fn instructions() {
+        use Instruction::*;
+
+        let brainfuck = Brainfuck::new("++[-]");
+
+        assert_eq!(&[Add(2), Clear], brainfuck.instructions());
+    }
+
+    #[test]
+    fn tape_window() {
+        let mut brainfuck = Brainfuck::new("");
+        brainfuck.preload(0, &[1, 2, 3, 4]).unwrap();
+        brainfuck.preload(29_997, &[5, 6, 7]).unwrap();
+
+        assert_eq!(&[2, 3, 4], brainfuck.tape_window(2, 1));
+        assert_eq!(&[1, 2, 3], brainfuck.tape_window(0, 2));
+        assert_eq!(&[5, 6, 7], brainfuck.tape_window(29_999, 2));
+        assert_eq!(&[7], brainfuck.tape_window(29_999, 0));
+        assert_eq!(30_000, brainfuck.tape_window(0, usize::MAX).len());
+        assert!(brainfuck.tape_window(40_000, 2).is_empty());
+    }
+
+    #[test]
+    fn input_retries_short_reads() {
+        struct Flaky(Vec<io::Result<u8>>);
+
+        impl io::Read for Flaky {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Ok(0);
+                }
+
+                let byte = self.0.remove(0)?;
+                buf[0] = byte;
+                Ok(1)
+            }
+        }
+
+        let mut input = Flaky(vec![
+            Ok(b'h'),
+            Err(io::ErrorKind::WouldBlock.into()),
+            Err(io::ErrorKind::Interrupted.into()),
+            Ok(b'i'),
+        ]);
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::new(",.,.,.");
+
+        assert_eq!(Ok(()), brainfuck.run(&mut input, &mut output));
+        assert_eq!(b"hi\0".to_vec(), output);
+    }
+
+    #[test]
+    fn input_would_block_stops() {
+        struct Blocked(Arc<AtomicBool>);
+
+        impl io::Read for Blocked {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                self.0.store(true, Ordering::Relaxed);
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+        }
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut brainfuck = BrainfuckBuilder::new("+,")
+            .interrupt(Arc::clone(&flag))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            Err(Error::Interrupted),
+            brainfuck.run(&mut Blocked(Arc::clone(&flag)), &mut io::sink())
+        );
+        assert_eq!(1, brainfuck.instruction_pointer());
+
+        let mut brainfuck = Brainfuck::new("+,");
+
+        assert_eq!(
+            Err(Error::Timeout),
+            brainfuck.run_with_timeout(
+                &mut Blocked(Arc::new(AtomicBool::new(false))),
+                &mut io::sink(),
+                Duration::from_millis(10),
+            )
+        );
+        assert_eq!(1, brainfuck.instruction_pointer());
+    }
+
+    #[test]
+    fn input_error() {
+        struct Broken;
+
+        impl io::Read for Broken {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::PermissionDenied.into())
+            }
+        }
+
+        let mut brainfuck = Brainfuck::new(",");
+
+        assert_eq!(
+            Err(Error::ReadError(io::ErrorKind::PermissionDenied.into())),
+            brainfuck.run(&mut Broken, &mut io::sink())
+        );
+    }
+
+    #[test]
+    fn set_tape_pointer() {
+        let mut brainfuck = Brainfuck::new("+");
+        brainfuck.set_tape_pointer(10).unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[0, 1, 0], brainfuck.tape(9..12));
+        assert_eq!(10, brainfuck.high_water_mark());
+        assert_eq!(Err(Error::TapeOverflow), brainfuck.set_tape_pointer(30_000));
+        assert_eq!(10, brainfuck.tape_pointer());
+    }
+
+    #[test]
+    fn out_decimal() {
+        let dialect = Dialect {
+            out_decimal: Some(':'),
+            ..Dialect::default()
+        };
+        let program = "+".repeat(200) + ":>:";
+
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::from_dialect(&program, &dialect);
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!(b"2000".to_vec(), output);
+    }
+
+    #[test]
+    fn record_and_replay_input() {
+        let program = ",[.,]";
+        let mut brainfuck = BrainfuckBuilder::new(program)
+            .record_input(true)
+            .build()
+            .unwrap();
+        let mut output = Vec::new();
+        brainfuck.run(&mut &b"flaky"[..], &mut output).unwrap();
+
+        assert_eq!(b"flaky", brainfuck.recorded_input());
+
+        let mut replayed = Vec::new();
+        Brainfuck::replay(program, brainfuck.recorded_input())
+            .run(&mut &b"ignored"[..], &mut replayed)
+            .unwrap();
+
+        assert_eq!(output, replayed);
+    }
+
+    #[test]
+    fn no_recording_by_default() {
+        let mut brainfuck = Brainfuck::new(",");
+        brainfuck.run(&mut &b"x"[..], &mut io::sink()).unwrap();
+
+        assert!(brainfuck.recorded_input().is_empty());
+    }
+
+    #[test]
+    fn out_unicode() {
+        let dialect = Dialect {
+            out_unicode: Some('u'),
+            ..Dialect::default()
+        };
+        let program = "+".repeat(0xe9) + "u>>-<-u";
+
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::from_dialect(&program, &dialect);
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!("\u{e9}\u{ffff}", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn out_unicode_surrogate() {
+        let dialect = Dialect {
+            out_unicode: Some('u'),
+            ..Dialect::default()
+        };
+        let program = ">".to_string() + &"+".repeat(0xd8) + "<u";
+
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::from_dialect(&program, &dialect);
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!("\u{fffd}", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn swap() {
+        let dialect = Dialect {
+            swap: Some('~'),
+            ..Dialect::default()
+        };
+
+        let mut brainfuck = Brainfuck::from_dialect(">~", &dialect);
+        brainfuck.preload(0, &[1, 2, 3]).unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[1, 3, 2], brainfuck.tape(0..3));
+        assert_eq!(1, brainfuck.tape_pointer());
+    }
+
+    #[test]
+    fn swap_at_right_boundary() {
+        let dialect = Dialect {
+            swap: Some('~'),
+            ..Dialect::default()
+        };
+
+        let mut brainfuck = Brainfuck::from_dialect("+~", &dialect);
+        brainfuck.set_tape_pointer(29_999).unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(1, brainfuck.current_cell());
+        assert_eq!(29_999, brainfuck.tape_pointer());
+
+        let mut brainfuck = Brainfuck::from_instructions(vec![Instruction::Swap]);
+        brainfuck.boundary_policy = BoundaryPolicy::Error;
+        brainfuck.set_tape_pointer(29_999).unwrap();
+
+        assert_eq!(Err(Error::TapeOverflow), brainfuck.run_pure());
+    }
+
+    #[test]
+    fn instruction_count() {
+        assert_eq!(1, Brainfuck::new("++++").instruction_count());
+        assert_eq!(
+            4,
+            BrainfuckBuilder::new("++++")
+                .opt_level(OptLevel::None)
+                .build()
+                .unwrap()
+                .instruction_count()
+        );
+    }
+
+    #[test]
+    fn optimize_disabled() {
+        use Instruction::*;
+
+        let build = |program| {
+            BrainfuckBuilder::new(program)
+                .optimize(false)
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(&[Add(1), Add(1), Add(1)], build("+++").instructions());
+        assert_eq!(&[Open, Sub(1), Close], build("[-]").instructions());
+        assert_eq!(
+            &[Add(3)],
+            BrainfuckBuilder::new("+++")
+                .optimize(true)
+                .build()
+                .unwrap()
+                .instructions()
+        );
+    }
+
+    #[test]
+    fn broken_pipe() {
+        struct Closed;
+
+        impl io::Write for Closed {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut brainfuck = BrainfuckBuilder::new("+.+.")
+            .stop_on_broken_pipe(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(Ok(()), brainfuck.run(&mut io::empty(), &mut Closed));
+        assert_eq!(1, brainfuck.current_cell());
+
+        let mut brainfuck = Brainfuck::new("+.+.");
+
+        assert_eq!(
+            Err(Error::WriteError(io::ErrorKind::BrokenPipe.into())),
+            brainfuck.run(&mut io::empty(), &mut Closed)
+        );
+    }
+
+    #[test]
+    fn step_reports_writes() {
+        let mut brainfuck = BrainfuckBuilder::new("+>+,")
+            .opt_level(OptLevel::None)
+            .build()
+            .unwrap();
+        let mut input = &b"x"[..];
+        let mut step = || brainfuck.step(&mut input, &mut io::sink());
+
+        assert_eq!(Ok(Some((0, 1))), step());
+        assert_eq!(Ok(None), step());
+        assert_eq!(Ok(Some((1, 1))), step());
+        assert_eq!(Ok(Some((1, b'x'))), step());
+        assert_eq!(Ok(None), step());
+    }
+
+    #[test]
+    fn move_reports_target_write() {
+        let mut brainfuck = Brainfuck::new("[->>+<<]");
+        brainfuck.preload(0, &[3]).unwrap();
+
+        assert_eq!(
+            Ok(Some((2, 3))),
+            brainfuck.step(&mut io::empty(), &mut io::sink())
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "past the end of the tape")]
+    fn pointer_past_end_asserts() {
+        let mut brainfuck = Brainfuck::new("+");
+        brainfuck.force_tape_pointer(30_000);
+        let _ = brainfuck.run_pure();
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn pointer_past_end_clamps() {
+        let mut brainfuck = Brainfuck::new("+");
+        brainfuck.force_tape_pointer(30_000);
+
+        assert_eq!(Ok(()), brainfuck.run_pure());
+        assert_eq!(&[1], brainfuck.tape(29_999..30_000));
+    }
+
+    #[test]
+    fn resume_with_chunks() {
+        let mut brainfuck = Brainfuck::new(",[.,]");
+        let mut output = Vec::new();
+
+        assert_eq!(
+            Ok(RunState::Suspended),
+            brainfuck.resume(&mut &b"hel"[..], &mut output)
+        );
+        assert_eq!(b"hel".to_vec(), output);
+        assert_eq!(
+            Ok(RunState::Suspended),
+            brainfuck.resume(&mut &b"lo"[..], &mut output)
+        );
+        assert_eq!(
+            Ok(RunState::Halted),
+            brainfuck.resume(&mut &b"\0"[..], &mut output)
+        );
+        assert_eq!(b"hello".to_vec(), output);
+    }
+
+    #[test]
+    fn error_from_io() {
+        fn fail() -> super::Result {
+            Err(io::Error::from(io::ErrorKind::NotFound))?;
+            Ok(())
+        }
+
+        let err = || io::Error::from(io::ErrorKind::NotFound);
+
+        assert_eq!(Err(Error::Io(err())), fail());
+        assert_eq!(Error::ReadError(err()), Error::from_read(err()));
+        assert_eq!(Error::WriteError(err()), Error::from_write(err()));
+        assert_ne!(Error::Io(err()), Error::from_write(err()));
+    }
+
+    #[test]
+    fn initial_dp() {
+        let mut brainfuck =
+            BrainfuckBuilder::new("<+").initial_dp(100).build().unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(99, brainfuck.tape_pointer());
+        assert_eq!(&[1, 0], brainfuck.tape(99..101));
+    }
+
+    #[test]
+    fn reset_to_initial_dp() {
+        let mut brainfuck =
+            BrainfuckBuilder::new(">+").initial_dp(100).build().unwrap();
+        brainfuck.run_pure().unwrap();
+        brainfuck.reset();
+
+        assert_eq!(100, brainfuck.tape_pointer());
+        assert_eq!(100, brainfuck.high_water_mark());
+
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(101, brainfuck.tape_pointer());
+        assert_eq!(&[0, 1], brainfuck.tape(100..102));
+    }
+
+    #[test]
+    fn initial_dp_out_of_range() {
+        assert_eq!(
+            Some(Error::TapeOverflow),
+            BrainfuckBuilder::new("").initial_dp(30_000).build().err()
+        );
+    }
+
+    #[test]
+    fn run_until_input() {
+        let mut brainfuck = Brainfuck::new(",.,.");
+        let mut output = Vec::new();
+
+        assert_eq!(
+            Ok(RunState::Suspended),
+            brainfuck.run_until_input(&mut output)
+        );
+        assert!(output.is_empty());
+
+        brainfuck.supply_input(b'o');
+
+        assert_eq!(
+            Ok(RunState::Suspended),
+            brainfuck.run_until_input(&mut output)
+        );
+        assert_eq!(b"o".to_vec(), output);
+
+        brainfuck.supply_input(b'k');
+
+        assert_eq!(Ok(RunState::Halted), brainfuck.run_until_input(&mut output));
+        assert_eq!(b"ok".to_vec(), output);
+    }
+
+    #[test]
+    fn grow_left() {
+        let mut brainfuck = BrainfuckBuilder::new("+<<+++>-<<<<<.")
+            .boundary_policy(BoundaryPolicy::GrowBoth)
+            .build()
+            .unwrap();
+        let mut output = Vec::new();
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        let origin = brainfuck.origin();
+
+        assert_eq!(vec!(0), output);
+        assert_eq!(-6, brainfuck.logical_pointer());
+        assert_eq!(60_000, brainfuck.tape_len());
+        assert_eq!(30_000, origin);
+        assert_eq!(&[3, 255, 1], brainfuck.tape(origin - 2..origin + 1));
+        assert_eq!(origin - 6, brainfuck.tape_pointer());
+        assert_eq!(origin, brainfuck.high_water_mark());
+    }
+
+    #[test]
+    fn grow_left_far() {
+        let program = "<".repeat(40_000) + "+" + &">".repeat(40_000) + "+";
+        let mut brainfuck = BrainfuckBuilder::new(&program)
+            .boundary_policy(BoundaryPolicy::GrowBoth)
+            .build()
+            .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(0, brainfuck.logical_pointer());
+        assert_eq!(40_000, brainfuck.origin());
+        assert_eq!(&[1], brainfuck.tape(0..1));
+        assert_eq!(1, brainfuck.current_cell());
+    }
+
+    #[test]
+    fn grow_past_memory() {
+        let policies = [BoundaryPolicy::Grow, BoundaryPolicy::GrowBoth];
+        let moves = [
+            Instruction::Right(usize::MAX / 2),
+            Instruction::Left(usize::MAX / 2),
+        ];
+
+        for (&policy, &instruction) in policies.iter().zip(&moves) {
+            let mut brainfuck = Brainfuck::from_instructions(vec![instruction]);
+            brainfuck.boundary_policy = policy;
+
+            assert_eq!(Err(Error::TapeOverflow), brainfuck.run_pure());
+            assert_eq!(30_000, brainfuck.tape_len());
+            assert_eq!(0, brainfuck.tape_pointer());
+        }
+    }
+
+    #[test]
+    fn move_past_usize() {
+        let policies = [
+            BoundaryPolicy::Clamp,
+            BoundaryPolicy::Error,
+            BoundaryPolicy::Grow,
+        ];
+        let results =
+            [Ok(()), Err(Error::TapeOverflow), Err(Error::TapeOverflow)];
+
+        for (&policy, result) in policies.iter().zip(results) {
+            let mut brainfuck = Brainfuck::from_instructions(vec![
+                Instruction::Right(1),
+                Instruction::Right(usize::MAX),
+            ]);
+            brainfuck.boundary_policy = policy;
+
+            assert_eq!(result, brainfuck.run_pure());
+        }
+    }
+
+    #[test]
+    fn grow_only_right() {
+        let mut brainfuck = BrainfuckBuilder::new("<<+")
+            .boundary_policy(BoundaryPolicy::Grow)
+            .build()
+            .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(0, brainfuck.logical_pointer());
+        assert_eq!(30_000, brainfuck.tape_len());
+    }
+
+    #[test]
+    fn repeated_output_writes_everything() {
+        struct Trickle(Vec<u8>);
+
+        impl io::Write for Trickle {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.extend_from_slice(&buf[..cmp::min(2, buf.len())]);
+                Ok(cmp::min(2, buf.len()))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut brainfuck = Brainfuck::from_instructions(vec![
+            Instruction::Add(65),
+            Instruction::OutN(5),
+        ]);
+        let mut output = Trickle(Vec::new());
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!(b"AAAAA".to_vec(), output.0);
+    }
+
+    #[test]
+    fn output_survives_empty_writes() {
+        struct Reluctant {
+            refusals: usize,
+            written: Vec<u8>,
+        }
+
+        impl io::Write for Reluctant {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.refusals > 0 {
+                    self.refusals -= 1;
+                    return Ok(0);
+                }
+
+                self.written.push(buf[0]);
+                Ok(1)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut output = Reluctant {
+            refusals: 1,
+            written: Vec::new(),
+        };
+        Brainfuck::new("+.+.")
+            .run(&mut io::empty(), &mut output)
+            .unwrap();
+
+        assert_eq!(vec!(1, 2), output.written);
+
+        let mut output = Reluctant {
+            refusals: usize::MAX,
+            written: Vec::new(),
+        };
+
+        assert_eq!(
+            Err(Error::WriteError(io::ErrorKind::WriteZero.into())),
+            Brainfuck::new("+.").run(&mut io::empty(), &mut output)
+        );
+    }
+
+    #[test]
+    fn is_halted() {
+        let mut brainfuck = BrainfuckBuilder::new("+++")
+            .opt_level(OptLevel::None)
+            .build()
+            .unwrap();
+
+        while !brainfuck.is_halted() {
+            brainfuck.step(&mut io::empty(), &mut io::sink()).unwrap();
+        }
+
+        assert_eq!(3, brainfuck.step_count());
+        assert_eq!(3, brainfuck.current_cell());
+
+        brainfuck.load("+");
+
+        assert!(!brainfuck.is_halted());
+        assert!(Brainfuck::new("").is_halted());
+    }
+
+    #[test]
+    fn render_tape() {
+        let mut brainfuck = Brainfuck::new("");
+        brainfuck.preload(0, &[72, 105, 10, 255]).unwrap();
+
+        assert_eq!("72 105 10 255", brainfuck.render_tape(0..4));
+
+        brainfuck.set_cell_format(CellFormat::Hex);
+
+        assert_eq!("48 69 0a ff", brainfuck.render_tape(0..4));
+
+        brainfuck.set_cell_format(CellFormat::Ascii);
+
+        assert_eq!("H i . .", brainfuck.render_tape(0..4));
+    }
+
+    #[test]
+    fn rewind_to() {
+        let mut brainfuck = Brainfuck::new("++[>+++<-]>.");
+        let step = |brainfuck: &mut Brainfuck| {
+            brainfuck.step(&mut io::empty(), &mut io::sink()).unwrap();
+        };
+
+        for _ in 0..5 {
+            step(&mut brainfuck);
+        }
+
+        assert_eq!(&[2, 3], brainfuck.tape(0..2));
+        assert_eq!(1, brainfuck.loop_depth());
+
+        brainfuck.rewind_to(2).unwrap();
+
+        assert_eq!(2, brainfuck.instruction_pointer());
+        assert_eq!(1, brainfuck.loop_depth());
+        assert_eq!(&[2, 3], brainfuck.tape(0..2));
+
+        step(&mut brainfuck);
+        step(&mut brainfuck);
+
+        assert_eq!(&[2, 6], brainfuck.tape(0..2));
+
+        brainfuck.set_tape_pointer(0).unwrap();
+        brainfuck.rewind_to(0).unwrap();
+        assert_eq!(0, brainfuck.loop_depth());
+
+        let mut output = Vec::new();
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!(vec![18], output);
+        assert_eq!(
+            Err(Error::InstructionOutOfRange),
+            brainfuck.rewind_to(brainfuck.instruction_count() + 1)
+        );
+    }
+
+    #[test]
+    fn step_over_loop() {
+        let mut brainfuck = Brainfuck::new("+++[->++<]>.");
+
+        brainfuck.step(&mut io::empty(), &mut io::sink()).unwrap();
+        assert_eq!(1, brainfuck.instruction_pointer());
+
+        brainfuck
+            .step_over_loop(&mut io::empty(), &mut io::sink())
+            .unwrap();
+
+        assert_eq!(7, brainfuck.instruction_pointer());
+        assert_eq!(&[0, 6], brainfuck.tape(0..2));
+
+        brainfuck
+            .step_over_loop(&mut io::empty(), &mut io::sink())
+            .unwrap();
+
+        assert_eq!(8, brainfuck.instruction_pointer());
+    }
+
+    #[test]
+    fn run_to_vec_with_capacity() {
+        let mut brainfuck = Brainfuck::new(",[.,]");
+
+        let output = brainfuck
+            .run_to_vec_with_capacity(&mut &b"abc"[..], 1024)
+            .unwrap();
+
+        assert_eq!(b"abc", &output[..]);
+        assert!(output.capacity() >= 1024);
+    }
+
+    #[test]
+    fn run_consuming() {
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::new(",,.");
+
+        let rest = brainfuck.run_consuming(&b"abc"[..], &mut output).unwrap();
+
+        assert_eq!(b"b", &output[..]);
+        assert_eq!(b"c", &rest[..]);
+    }
+
+    #[test]
+    fn flush_mode() {
+        #[derive(Default)]
+        struct Flushes {
+            written: Vec<u8>,
+            flushed_at: Vec<usize>,
+        }
+
+        impl io::Write for Flushes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.written.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushed_at.push(self.written.len());
+                Ok(())
+            }
+        }
+
+        let run = |mode| {
+            let mut output = Flushes::default();
+            let mut brainfuck = BrainfuckBuilder::new(",.,.,.,.")
+                .flush_mode(mode)
+                .build()
+                .unwrap();
+            brainfuck.run(&mut &b"a\nb\n"[..], &mut output).unwrap();
+
+            assert_eq!(b"a\nb\n", &output.written[..]);
+            output.flushed_at
+        };
+
+        assert_eq!(Vec::<usize>::new(), run(FlushMode::Never));
+        assert_eq!(vec![2, 4], run(FlushMode::Newline));
+        assert_eq!(vec![1, 2, 3, 4], run(FlushMode::Always));
+    }
+
+    #[test]
+    fn run_tee() {
+        let mut primary = Vec::new();
+        let mut secondary = Vec::new();
+        let mut brainfuck = Brainfuck::new(",[.,]");
+
+        brainfuck
+            .run_tee(&mut &b"tee"[..], &mut primary, &mut secondary)
+            .unwrap();
+
+        assert_eq!(b"tee", &primary[..]);
+        assert_eq!(primary, secondary);
+
+        let mut full = [0u8; 2];
+        let mut brainfuck = Brainfuck::new("+...");
+
+        assert_eq!(
+            Err(Error::WriteError(io::ErrorKind::WriteZero.into())),
+            brainfuck.run_tee(
+                &mut io::empty(),
+                &mut Vec::new(),
+                &mut &mut full[..]
+            )
+        );
+    }
+
+    #[test]
+    fn run_with_output_limit() {
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::new("+[.]");
+
+        assert_eq!(
+            Err(Error::OutputLimitExceeded),
+            brainfuck.run_with_output_limit(&mut io::empty(), &mut output, 100)
+        );
+        assert_eq!(vec![1; 100], output);
+
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::new("++++++++[>++++++++<-]>+..");
+
+        assert_eq!(
+            Ok(()),
+            brainfuck.run_with_output_limit(&mut io::empty(), &mut output, 2)
+        );
+        assert_eq!(b"AA", &output[..]);
+    }
+
+    #[test]
+    fn cell() {
+        let mut brainfuck = Brainfuck::new(">>>>>+++");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(Some(3), brainfuck.cell(5));
+        assert_eq!(Some(0), brainfuck.cell(4));
+        assert_eq!(Some(0), brainfuck.cell(29_999));
+        assert_eq!(None, brainfuck.cell(30_000));
+    }
+
+    #[test]
+    fn describe_error() {
+        let mut brainfuck =
+            BrainfuckBuilder::new("[").name("sieve.bf").build().unwrap();
+        let err = brainfuck.run_pure().unwrap_err();
+
+        assert_eq!(Some("sieve.bf"), brainfuck.name());
+        assert_eq!(
+            "program 'sieve.bf': unbalanced parentheses",
+            brainfuck.describe(&err)
+        );
+        assert_eq!("unbalanced parentheses", Brainfuck::new("").describe(&err));
+    }
+
+    #[test]
+    fn current_cell() {
+        let mut brainfuck = Brainfuck::new("+++");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(3, brainfuck.current_cell());
+    }
+
+    #[test]
+    fn step_count() {
+        let mut brainfuck = Brainfuck::new("++[->++<]");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(12, brainfuck.step_count());
+
+        brainfuck.reset();
+
+        assert_eq!(0, brainfuck.step_count());
+    }
+
+    #[test]
+    fn boundary_clamp() {
+        let program = ">".repeat(30_005) + "+";
+        let mut brainfuck = BrainfuckBuilder::new(&program)
+            .boundary_policy(BoundaryPolicy::Clamp)
+            .build()
+            .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(29_999, brainfuck.tape_pointer());
+        assert_eq!(&[1], brainfuck.tape(29_999..30_000));
+    }
+
+    #[test]
+    fn boundary_error() {
+        let program = ">".repeat(29_999) + "+>+";
+        let mut brainfuck = BrainfuckBuilder::new(&program)
+            .boundary_policy(BoundaryPolicy::Error)
+            .build()
+            .unwrap();
+
+        match brainfuck.run_pure() {
+            Err(Error::TapeOverflow) => {}
+            result => panic!("expected tape overflow, got {:?}", result),
+        }
+
+        assert_eq!(29_999, brainfuck.tape_pointer());
+        assert_eq!(&[1], brainfuck.tape(29_999..30_000));
+    }
+
+    #[test]
+    fn boundary_grow() {
+        let program = ">".repeat(30_005) + "+";
+        let mut brainfuck = BrainfuckBuilder::new(&program)
+            .boundary_policy(BoundaryPolicy::Grow)
+            .build()
+            .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(30_005, brainfuck.tape_pointer());
+        assert_eq!(60_000, brainfuck.tape_len());
+        assert_eq!(&[0, 1, 0], brainfuck.tape(30_004..30_007));
+    }
+
+    #[test]
+    fn instruction_less_than() {
+        let mut brainfuck = Brainfuck::new("<");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(0, brainfuck.tape_pointer());
+    }
+
+    #[test]
+    fn instruction_less_than_2() {
+        let mut brainfuck = Brainfuck::new(">><");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(1, brainfuck.tape_pointer());
+    }
+
+    #[test]
+    fn pointer_wrap_left() {
+        let mut brainfuck = BrainfuckBuilder::new("<+")
+            .pointer_mode(PointerMode::Wrap)
+            .build()
+            .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(29_999, brainfuck.tape_pointer());
+        assert_eq!(&[1], brainfuck.tape(29_999..30_000));
+    }
+
+    #[test]
+    fn pointer_wrap_right() {
+        let program = ">".repeat(30_000);
+        let mut brainfuck = BrainfuckBuilder::new(&program)
+            .pointer_mode(PointerMode::Wrap)
+            .build()
+            .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(0, brainfuck.tape_pointer());
+
+        brainfuck.load(">>>");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(3, brainfuck.tape_pointer());
+    }
+
+    #[test]
+    fn instruction_plus() {
+        let mut brainfuck = Brainfuck::new("+");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[1], brainfuck.tape(0..1));
+    }
+
+    #[test]
+    fn instruction_plus_2() {
+        let mut brainfuck = Brainfuck::new("++>++>++");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[2, 2, 2], brainfuck.tape(0..3));
+    }
+
+    #[test]
+    fn instruction_minus() {
+        let mut brainfuck = Brainfuck::new("-");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[255], brainfuck.tape(0..1));
+    }
+
+    #[test]
+    fn instruction_minus_2() {
+        let mut brainfuck = Brainfuck::new("-->-->--");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[254, 254, 254], brainfuck.tape(0..3));
+    }
+
+    #[test]
+    fn cell_arith_wrap() {
+        let mut brainfuck =
+            BrainfuckBuilder::new("->+++[>++++++++++<-]>[>+++++++++<-]>+")
+                .cell_arith(CellArith::Wrap)
+                .build()
+                .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[255, 0, 0, 15], brainfuck.tape(0..4));
+    }
+
+    #[test]
+    fn cell_arith_saturate() {
+        let mut brainfuck =
+            BrainfuckBuilder::new("->+++[>++++++++++<-]>[>+++++++++<-]>+")
+                .cell_arith(CellArith::Saturate)
+                .build()
+                .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[0, 0, 0, 255], brainfuck.tape(0..4));
+    }
+
+    #[test]
+    fn cell_arith_saturate_optimized() {
+        for program in &["-+.", "+++++--->-+++<.>."] {
+            let run = |level| {
+                let mut output = Vec::new();
+                BrainfuckBuilder::new(program)
+                    .cell_arith(CellArith::Saturate)
+                    .opt_level(level)
+                    .build()
+                    .unwrap()
+                    .run(&mut io::empty(), &mut output)
+                    .unwrap();
+                output
+            };
+
+            assert_eq!(run(OptLevel::None), run(OptLevel::Full));
+        }
+    }
+
+    #[test]
+    fn transfer_at_edges() {
+        let cases = [
+            ("+[-<+>]", 0, BoundaryPolicy::Clamp),
+            ("+++[-<<+>>]>+<", 1, BoundaryPolicy::Clamp),
+            ("++[->+<]", 29_999, BoundaryPolicy::Clamp),
+            ("++[->+<]", 29_999, BoundaryPolicy::Error),
+            ("++[->>+<<]", 29_998, BoundaryPolicy::Grow),
+            ("++[>+<-]", 29_999, BoundaryPolicy::Grow),
+            ("++[<+>-]", 0, BoundaryPolicy::GrowBoth),
+            ("><+", 29_999, BoundaryPolicy::Error),
+            ("<>+", 0, BoundaryPolicy::Clamp),
+            ("><+", 29_999, BoundaryPolicy::Grow),
+        ];
+
+        for &(program, dp, policy) in &cases {
+            let build = |level| {
+                BrainfuckBuilder::new(program)
+                    .initial_dp(dp)
+                    .boundary_policy(policy)
+                    .opt_level(level)
+                    .build()
+                    .unwrap()
+            };
+            let mut plain = build(OptLevel::None);
+            let mut optimized = build(OptLevel::Full);
+
+            assert_eq!(
+                program.contains('['),
+                optimized
+                    .instructions()
+                    .iter()
+                    .any(|i| matches!(i, Instruction::Move { .. }))
+            );
+            assert_eq!(plain.run_pure(), optimized.run_pure(), "{}", program);
+            assert_eq!(plain.tape_pointer(), optimized.tape_pointer());
+
+            let len = plain.tape_len();
+            assert_eq!(len, optimized.tape_len());
+            assert_eq!(plain.tape(0..len), optimized.tape(0..len));
+        }
+    }
+
+    #[test]
+    fn unoptimized() {
+        let mut brainfuck = BrainfuckBuilder::new("+++>++<-")
+            .opt_level(OptLevel::None)
+            .build()
+            .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(8, brainfuck.instructions.len());
+        assert_eq!(&[2, 2], brainfuck.tape(0..2));
+
+        brainfuck.load("++");
+        assert_eq!(2, brainfuck.instructions.len());
+    }
+
+    #[test]
+    fn instruction_dot() {
+        let mut output: Vec<u8> = Vec::new();
+        let mut brainfuck = Brainfuck::new(".");
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!(vec!(0), output);
+    }
+
+    #[test]
+    fn instruction_dot_2() {
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::new("+>++>+++.<.<.");
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!(vec!(3, 2, 1), output);
+    }
+
+    #[test]
+    fn instruction_comma() {
+        let input = [5, 4, 3];
+        let mut brainfuck = Brainfuck::new(",>,>,");
+        brainfuck.run(&mut input.as_ref(), &mut io::sink()).unwrap();
+
+        assert_eq!(&[5, 4, 3], brainfuck.tape(0..3));
+    }
+
+    #[test]
+    fn instruction_comma_2() {
+        let input = [5, 4, 3];
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::new(",.>,.>,.");
+        brainfuck.run(&mut input.as_ref(), &mut output).unwrap();
+
+        assert_eq!(vec!(5, 4, 3), output);
+    }
+
+    #[test]
+    fn compile_once() {
+        let program = super::compile(
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---\
+             .+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.\n",
+        );
+
+        for _ in 0..2 {
+            let mut output = Vec::new();
+            let mut brainfuck = Brainfuck::from_compiled(program.clone());
+            brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+            assert_eq!("Hello World!\n", String::from_utf8(output).unwrap());
+            assert!(std::ptr::eq(
+                program.instructions(),
+                brainfuck.instructions()
+            ));
+        }
+    }
+
+    #[test]
+    fn run_cases() {
+        super::run_cases(
+            ",.",
+            &[(b"a", b"a"), (b"xyz", b"x"), (b"", b"\0"), (&[200], &[200])],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "case 1 printed wrong output")]
+    fn run_cases_mismatch() {
+        super::run_cases(",.", &[(b"a", b"a"), (b"b", b"c")]);
+    }
+
+    #[test]
+    fn hello_world() {
+        let mut brainfuck = Brainfuck::new(
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---\
+             .+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.\n",
+        );
+
+        let mut output = Vec::new();
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!("Hello World!\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn hello_world_complex() {
+        let mut brainfuck = Brainfuck::new(
+            ">++++++++[-<+++++++++>]<.>>+>-[+]++>++>+++[>[->+++<<+++>]<<]\
+             >-----.>->+++..+++.>-.<<+[>[+>+]>>]<--------------.>>.+++.---\
+             ---.--------.>+.>+.",
+        );
+
+        let mut output = Vec::new();
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!("Hello World!\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let mut brainfuck = Brainfuck::new("++>+++[<+>-]<.>++++.");
 
-        Brainfuck {
-            instructions: optimized_instructions,
-            ip: 0,
-            tape: [0; TAPE_SIZE],
-            dp: 0,
-            stack: Vec::new(),
+        for _ in 0..5 {
+            brainfuck.step(&mut io::empty(), &mut io::sink()).unwrap();
         }
-    }
 
-    #[allow(dead_code)]
-    pub fn tape_pointer(&self) -> usize {
-        self.dp
-    }
+        let snapshot = brainfuck.snapshot();
 
-    #[allow(dead_code)]
-    pub fn tape(&self, range: Range<usize>) -> &[u8] {
-        &self.tape[range]
-    }
+        let mut first = Vec::new();
+        brainfuck.run(&mut io::empty(), &mut first).unwrap();
 
-    #[allow(dead_code)]
-    pub fn run_pure(&mut self) -> Result {
-        self.run(&mut io::empty(), &mut io::sink())
+        brainfuck.restore(&snapshot);
+        assert_eq!(snapshot, brainfuck.snapshot());
+
+        let mut second = Vec::new();
+        brainfuck.run(&mut io::empty(), &mut second).unwrap();
+
+        assert_eq!(vec!(5, 4), first);
+        assert_eq!(first, second);
     }
 
-    pub fn run<R, W>(&mut self, input: &mut R, output: &mut W) -> Result
-    where
-        R: io::Read,
-        W: io::Write,
-    {
-        loop {
-            match self.current() {
-                Some(&Instruction::Right(n)) => {
-                    if self.dp + n < self.tape.len() - 1 {
-                        self.dp += n;
-                    } else {
-                        self.dp = self.tape.len();
-                    }
-                }
-                Some(&Instruction::Left(n)) => {
-                    self.dp = self.dp.saturating_sub(n);
-                }
-                Some(&Instruction::Add(n)) => {
-                    let byte = self.get_byte().wrapping_add(n);
-                    self.set_byte(byte);
-                }
-                Some(&Instruction::Sub(n)) => {
-                    let byte = self.get_byte().wrapping_sub(n);
-                    self.set_byte(byte);
-                }
-                Some(&Instruction::Out) => {
-                    let _ = output
-                        .write(&[self.get_byte()])
-                        .map_err(Error::WriteError)?;
-                }
-                Some(&Instruction::In) => {
-                    let mut buffer = [0; 1];
-                    let _ = input.read(&mut buffer).map_err(Error::ReadError)?;
-                    self.set_byte(buffer[0]);
-                }
-                Some(&Instruction::Open) => {
-                    if self.get_byte() == 0 {
-                        self.advance_to_matching_paren();
-                    } else {
-                        self.push();
-                    }
-                }
-                Some(&Instruction::Close) => {
-                    if self.get_byte() != 0 {
-                        self.return_to_matching_paren()?;
-                    } else {
-                        self.pop();
-                    }
-                }
-                None => {
-                    break;
-                }
-            };
+    #[test]
+    fn clone_diverges() {
+        let mut brainfuck = Brainfuck::new(",.,.");
+        brainfuck.step(&mut [1].as_ref(), &mut io::sink()).unwrap();
 
-            self.advance();
-        }
+        let mut clone = brainfuck.clone();
 
-        Ok(())
-    }
+        let mut output = Vec::new();
+        brainfuck.run(&mut [2].as_ref(), &mut output).unwrap();
 
-    #[inline(always)]
-    fn set_byte(&mut self, byte: u8) {
-        self.tape[self.dp] = byte;
-    }
+        let mut clone_output = Vec::new();
+        clone.run(&mut [3].as_ref(), &mut clone_output).unwrap();
 
-    #[inline(always)]
-    fn get_byte(&self) -> u8 {
-        self.tape[self.dp]
+        assert_eq!(vec!(1, 2), output);
+        assert_eq!(vec!(1, 3), clone_output);
+        assert_eq!(&[2], brainfuck.tape(0..1));
+        assert_eq!(&[3], clone.tape(0..1));
     }
 
-    #[inline(always)]
-    fn advance(&mut self) {
-        self.ip += 1;
+    #[test]
+    fn debug_summary() {
+        let mut brainfuck = Brainfuck::new(">>>>>>>>>>+");
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(
+            "Brainfuck { ip: 2, dp: 10, instructions: 2, tape_offset: 2, \
+             tape: [0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0] }",
+            format!("{:?}", brainfuck)
+        );
     }
 
-    #[inline(always)]
-    fn current(&self) -> Option<&Instruction> {
-        self.instructions.get(self.ip)
+    #[test]
+    fn output_iter() {
+        let brainfuck = Brainfuck::new(
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---\
+             .+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.\n",
+        );
+
+        let output = brainfuck
+            .output_iter(io::empty())
+            .collect::<Result<Vec<u8>, _>>()
+            .unwrap();
+
+        assert_eq!("Hello World!\n", String::from_utf8(output).unwrap());
     }
 
-    #[inline(always)]
-    fn pop(&mut self) {
-        let _ = self.stack.pop();
+    #[test]
+    fn output_iter_lazy() {
+        let brainfuck = Brainfuck::new(",.,.");
+        let mut iter = brainfuck.output_iter([7, 8].as_ref());
+
+        assert_eq!(7, iter.next().unwrap().unwrap());
+        assert_eq!(&[8], iter.input);
+        assert_eq!(8, iter.next().unwrap().unwrap());
+        assert!(iter.next().is_none());
     }
 
-    #[inline(always)]
-    fn push(&mut self) {
-        self.stack.push(self.ip);
+    #[test]
+    fn from_file() {
+        let path = env::temp_dir()
+            .join(format!("brainfuck-from-file-{}.bf", std::process::id()));
+        fs::write(&path, "+.").unwrap();
+
+        let mut brainfuck = Brainfuck::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut output = Vec::new();
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!(vec!(1), output);
     }
 
-    #[inline(always)]
-    fn advance_to_matching_paren(&mut self) {
-        let mut c = 0;
+    #[test]
+    fn from_file_with_shebang() {
+        let path = env::temp_dir()
+            .join(format!("brainfuck-shebang-{}.bf", std::process::id()));
+        fs::write(&path, "#!/usr/bin/env -S brainfuck --input in.txt\n+.")
+            .unwrap();
 
-        loop {
-            self.advance();
+        let mut brainfuck = Brainfuck::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
 
-            match self.current() {
-                None | Some(&Instruction::Close) if c == 0 => break,
-                Some(&Instruction::Close) => c -= 1,
-                Some(&Instruction::Open) => c += 1,
-                _ => {}
-            }
-        }
+        let mut output = Vec::new();
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!(vec!(1), output);
     }
 
-    #[inline(always)]
-    fn return_to_matching_paren(&mut self) -> Result {
-        match self.stack.last() {
-            Some(ip) => {
-                self.ip = *ip;
-            }
-            None => return Err(Error::UnbalancedParens),
-        }
+    #[cfg(feature = "memmap2")]
+    #[test]
+    fn from_mmap() {
+        let path = env::temp_dir()
+            .join(format!("brainfuck-from-mmap-{}.bf", std::process::id()));
+        fs::write(&path, "#!/usr/bin/env brainfuck\n++[->+++<]>.").unwrap();
 
-        Ok(())
+        let mut brainfuck = Brainfuck::from_mmap(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut output = Vec::new();
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!(vec!(6), output);
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::Brainfuck;
-    use std::io;
+    #[test]
+    fn from_missing_file() {
+        assert!(Brainfuck::from_file("/nonexistent/program.bf").is_err());
+    }
 
     #[test]
-    fn initialized() {
-        let brainfuck = Brainfuck::new("");
+    fn load_keeps_tape() {
+        let mut brainfuck = Brainfuck::new("+++>++");
+        brainfuck.run_pure().unwrap();
+
+        brainfuck.load("+<+");
+        brainfuck.run_pure().unwrap();
 
         assert_eq!(0, brainfuck.tape_pointer());
-        assert_eq!(&[0, 0, 0, 0], brainfuck.tape(0..4));
+        assert_eq!(&[4, 3], brainfuck.tape(0..2));
     }
 
     #[test]
-    fn instruction_greater_than() {
-        let mut brainfuck = Brainfuck::new(">");
+    fn reset() {
+        let mut brainfuck = Brainfuck::new("+++>++");
         brainfuck.run_pure().unwrap();
+        brainfuck.reset();
 
-        assert_eq!(1, brainfuck.tape_pointer());
+        assert_eq!(0, brainfuck.tape_pointer());
+        assert_eq!(&[0, 0], brainfuck.tape(0..2));
+
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[3, 2], brainfuck.tape(0..2));
     }
 
     #[test]
-    fn instruction_less_than() {
-        let mut brainfuck = Brainfuck::new("<");
-        brainfuck.run_pure().unwrap();
+    fn words_hello_world() {
+        let program = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---\
+                       .+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let words = program
+            .chars()
+            .map(|c| match c {
+                '+' => "incr ",
+                '-' => "decr ",
+                '>' => "next\n",
+                '<' => "prev\n",
+                '.' => "print\t",
+                ',' => "read ",
+                '[' => "loop\n",
+                ']' => "end\n",
+                _ => "",
+            })
+            .collect::<String>();
 
-        assert_eq!(0, brainfuck.tape_pointer());
+        let mut brainfuck =
+            Brainfuck::from_words(&words, &WordDialect::default());
+
+        let mut output = Vec::new();
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!("Hello World!\n", String::from_utf8(output).unwrap());
     }
 
     #[test]
-    fn instruction_less_than_2() {
-        let mut brainfuck = Brainfuck::new(">><");
-        brainfuck.run_pure().unwrap();
+    fn ook_hello_world() {
+        let program = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---\
+                       .+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let ook = program
+            .chars()
+            .map(|c| match c {
+                '+' => "Ook. Ook. ",
+                '-' => "Ook! Ook! ",
+                '>' => "Ook. Ook? ",
+                '<' => "Ook? Ook. ",
+                '.' => "Ook! Ook. ",
+                ',' => "Ook. Ook! ",
+                '[' => "Ook! Ook? ",
+                ']' => "Ook? Ook! ",
+                _ => "",
+            })
+            .collect::<String>();
 
-        assert_eq!(1, brainfuck.tape_pointer());
+        let mut brainfuck = Brainfuck::from_ook(&ook);
+
+        let mut output = Vec::new();
+        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!("Hello World!\n", String::from_utf8(output).unwrap());
     }
 
     #[test]
-    fn instruction_plus() {
-        let mut brainfuck = Brainfuck::new("+");
+    fn swapped_dialect() {
+        let dialect = Dialect {
+            add: '-',
+            sub: '+',
+            ..Dialect::default()
+        };
+
+        let mut brainfuck = Brainfuck::from_dialect("-->+", &dialect);
         brainfuck.run_pure().unwrap();
 
-        assert_eq!(&[1], brainfuck.tape(0..1));
+        assert_eq!(&[2, 255], brainfuck.tape(0..2));
     }
 
     #[test]
-    fn instruction_plus_2() {
-        let mut brainfuck = Brainfuck::new("++>++>++");
-        brainfuck.run_pure().unwrap();
+    fn run_traced() {
+        let mut trace = Vec::new();
+        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::new("++>.");
+        brainfuck
+            .run_traced(&mut io::empty(), &mut output, &mut trace)
+            .unwrap();
 
-        assert_eq!(&[2, 2, 2], brainfuck.tape(0..3));
+        assert_eq!(vec!(0), output);
+        assert_eq!(
+            "{\"ip\": 0, \"dp\": 0, \"instr\": \"Add\", \"arg\": 2, \"cell\": 2}\n\
+             {\"ip\": 1, \"dp\": 1, \"instr\": \"Right\", \"arg\": 1, \"cell\": 0}\n\
+             {\"ip\": 2, \"dp\": 1, \"instr\": \"Out\", \"arg\": null, \"cell\": 0}\n",
+            String::from_utf8(trace).unwrap()
+        );
     }
 
     #[test]
-    fn instruction_minus() {
-        let mut brainfuck = Brainfuck::new("-");
+    fn instruction_pointer_and_loop_depth() {
+        let mut brainfuck = Brainfuck::new("+[>++[--]<-]");
+
+        assert_eq!(0, brainfuck.instruction_pointer());
+        assert_eq!(0, brainfuck.loop_depth());
+
+        for _ in 0..5 {
+            brainfuck.step(&mut io::empty(), &mut io::sink()).unwrap();
+        }
+
+        assert_eq!(5, brainfuck.instruction_pointer());
+        assert_eq!(2, brainfuck.loop_depth());
+
         brainfuck.run_pure().unwrap();
 
-        assert_eq!(&[255], brainfuck.tape(0..1));
+        assert_eq!(0, brainfuck.loop_depth());
     }
 
     #[test]
-    fn instruction_minus_2() {
-        let mut brainfuck = Brainfuck::new("-->-->--");
-        brainfuck.run_pure().unwrap();
+    fn interrupt() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut brainfuck = BrainfuckBuilder::new("+[]")
+            .interrupt(Arc::clone(&flag))
+            .build()
+            .unwrap();
 
-        assert_eq!(&[254, 254, 254], brainfuck.tape(0..3));
+        let handle = thread::spawn(move || brainfuck.run_pure());
+
+        thread::sleep(Duration::from_millis(10));
+        flag.store(true, Ordering::Relaxed);
+
+        match handle.join().unwrap() {
+            Err(Error::Interrupted) => {}
+            result => panic!("expected interruption, got {:?}", result),
+        }
     }
 
     #[test]
-    fn instruction_dot() {
-        let mut output: Vec<u8> = Vec::new();
-        let mut brainfuck = Brainfuck::new(".");
-        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+    fn interrupt_before_run() {
+        let flag = Arc::new(AtomicBool::new(true));
+        let mut brainfuck = BrainfuckBuilder::new("+")
+            .interrupt(Arc::clone(&flag))
+            .build()
+            .unwrap();
 
-        assert_eq!(vec!(0), output);
+        assert!(brainfuck.run_pure().is_err());
+        assert_eq!(&[0], brainfuck.tape(0..1));
+
+        flag.store(false, Ordering::Relaxed);
+
+        assert!(brainfuck.run_pure().is_ok());
+        assert_eq!(&[1], brainfuck.tape(0..1));
     }
 
     #[test]
-    fn instruction_dot_2() {
+    fn nested_round_trip() {
+        let program = "++[>+++[>++[>+<-]<-]<-]>>>.+[>[-]+++[>+++<-]>.<<-]";
+        let compiled = compile(program);
+        let nodes = optimizer::nest(compiled.instructions()).unwrap();
+
+        let mut expected = Vec::new();
+        Brainfuck::from_compiled(compiled)
+            .run(&mut io::empty(), &mut expected)
+            .unwrap();
+
         let mut output = Vec::new();
-        let mut brainfuck = Brainfuck::new("+>++>+++.<.<.");
-        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+        Brainfuck::from_nodes(&nodes)
+            .run(&mut io::empty(), &mut output)
+            .unwrap();
 
-        assert_eq!(vec!(3, 2, 1), output);
+        assert_eq!(expected, output);
+        assert!(!output.is_empty());
     }
 
     #[test]
-    fn instruction_comma() {
-        let input = [5, 4, 3];
-        let mut brainfuck = Brainfuck::new(",>,>,");
-        brainfuck.run(&mut input.as_ref(), &mut io::sink()).unwrap();
+    fn nested_loops_match_unoptimized() {
+        let programs = [
+            "++[>+++[>++[>+<-]<-]<-]>>>.",
+            "+++[>++[-]<-]+[>[-]+++[>+++<-]>.<<-]",
+            "[[[+]]]>+[>[>[-]]]<.",
+        ];
 
-        assert_eq!(&[5, 4, 3], brainfuck.tape(0..3));
+        for program in &programs {
+            let mut expected = Vec::new();
+            BrainfuckBuilder::new(program)
+                .opt_level(OptLevel::None)
+                .build()
+                .unwrap()
+                .run(&mut io::empty(), &mut expected)
+                .unwrap();
+
+            let mut output = Vec::new();
+            Brainfuck::new(program)
+                .run(&mut io::empty(), &mut output)
+                .unwrap();
+
+            assert_eq!(expected, output);
+            assert!(!output.is_empty());
+        }
     }
 
     #[test]
-    fn instruction_comma_2() {
-        let input = [5, 4, 3];
+    fn unmatched_open_fails() {
         let mut output = Vec::new();
-        let mut brainfuck = Brainfuck::new(",.>,.>,.");
-        brainfuck.run(&mut input.as_ref(), &mut output).unwrap();
+        let mut brainfuck = Brainfuck::new(".[.");
+        let result = brainfuck.run(&mut io::empty(), &mut output);
 
-        assert_eq!(vec!(5, 4, 3), output);
+        assert!(matches!(result, Err(Error::UnbalancedParens)));
+        assert_eq!(vec!(0), output);
     }
 
     #[test]
-    fn hello_world() {
-        let mut brainfuck = Brainfuck::new(
-            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---\
-             .+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.\n",
-        );
+    fn timeout() {
+        let timeout = Duration::from_millis(50);
 
+        let mut brainfuck = Brainfuck::new("+[]");
+        match brainfuck.run_with_timeout(
+            &mut io::empty(),
+            &mut io::sink(),
+            timeout,
+        ) {
+            Err(Error::Timeout) => {}
+            result => panic!("expected timeout, got {:?}", result),
+        }
+
+        let mut brainfuck = Brainfuck::new("+++[>+++<-]>.");
         let mut output = Vec::new();
-        brainfuck.run(&mut io::empty(), &mut output).unwrap();
+        brainfuck
+            .run_with_timeout(&mut io::empty(), &mut output, timeout)
+            .unwrap();
+
+        assert_eq!(vec!(9), output);
+    }
+
+    #[test]
+    fn run_with_sink() {
+        let program = ">++++++++[-<+++++++++>]<.>>+>-[+]++>++>+++[>[->+++<<+++>]<<]\
+                       >-----.>->+++..+++.>-.<<+[>[+>+]>>]<--------------.>>.+++.---\
+                       ---.--------.>+.>+.";
+
+        let mut expected = Vec::new();
+        Brainfuck::new(program)
+            .run(&mut io::empty(), &mut expected)
+            .unwrap();
 
+        let mut output = Vec::new();
+        Brainfuck::new(program)
+            .run_with_sink(&mut io::empty(), |byte| output.push(byte))
+            .unwrap();
+
+        assert_eq!(expected, output);
         assert_eq!("Hello World!\n", String::from_utf8(output).unwrap());
     }
 
     #[test]
-    fn hello_world_complex() {
-        let mut brainfuck = Brainfuck::new(
-            ">++++++++[-<+++++++++>]<.>>+>-[+]++>++>+++[>[->+++<<+++>]<<]\
-             >-----.>->+++..+++.>-.<<+[>[+>+]>>]<--------------.>>.+++.---\
-             ---.--------.>+.>+.",
+    fn try_new() {
+        assert_eq!(
+            ParseError::UnmatchedOpen(2),
+            Brainfuck::try_new("++[>+").unwrap_err()
         );
 
-        let mut output = Vec::new();
+        let mut brainfuck = Brainfuck::try_new("++[>+<-]").unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[0, 2], brainfuck.tape(0..2));
+    }
+
+    #[test]
+    fn profile() {
+        let mut brainfuck = BrainfuckBuilder::new("+++[>++<-]+[-]")
+            .profiling(true)
+            .build()
+            .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        let expected = [
+            ("Add", 5),
+            ("Open", 1),
+            ("Right", 3),
+            ("Left", 3),
+            ("Sub", 3),
+            ("Close", 3),
+            ("Clear", 1),
+        ]
+        .iter()
+        .cloned()
+        .collect::<HashMap<_, _>>();
+
+        assert_eq!(expected, brainfuck.profile());
+        assert_eq!(&[1, 1, 3, 3, 3, 3, 3, 1, 1], brainfuck.instruction_counts());
+    }
+
+    #[test]
+    fn profile_skipped_loop() {
+        let mut brainfuck = BrainfuckBuilder::new("[>]+")
+            .profiling(true)
+            .build()
+            .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        assert_eq!(&[1, 0, 0, 1], brainfuck.instruction_counts());
+    }
+
+    #[test]
+    fn loop_iterations() {
+        let mut brainfuck = BrainfuckBuilder::new("+++[-]>[+]++[->++[-]<]")
+            .profiling(true)
+            .optimize(false)
+            .build()
+            .unwrap();
+        brainfuck.run_pure().unwrap();
+
+        let expected = [(3, 3), (7, 0), (12, 2), (17, 4)]
+            .iter()
+            .cloned()
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(expected, brainfuck.loop_iterations());
+        assert!(Brainfuck::new("+[-]").loop_iterations().is_empty());
+    }
+
+    #[test]
+    fn profile_disabled() {
+        let mut brainfuck = Brainfuck::new("+++");
+        brainfuck.run_pure().unwrap();
+
+        assert!(brainfuck.profile().is_empty());
+        assert!(brainfuck.instruction_counts().is_empty());
+    }
+
+    #[test]
+    fn repeated_output_single_write() {
+        struct CountingWriter {
+            output: Vec<u8>,
+            writes: usize,
+        }
+
+        impl io::Write for CountingWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.writes += 1;
+                self.output.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut output = CountingWriter {
+            output: Vec::new(),
+            writes: 0,
+        };
+        let program = "+".repeat(65) + "....";
+        let mut brainfuck = Brainfuck::new(&program);
         brainfuck.run(&mut io::empty(), &mut output).unwrap();
 
-        assert_eq!("Hello World!\n", String::from_utf8(output).unwrap());
+        assert_eq!(b"AAAA", output.output.as_slice());
+        assert_eq!(1, output.writes);
+    }
+
+    #[test]
+    fn output_modes() {
+        let program = "+".repeat(200) + ".>" + &"+".repeat(65) + "..>++++++++++.";
+        let run = |mode| {
+            let mut output = Vec::new();
+            BrainfuckBuilder::new(&program)
+                .output_mode(mode)
+                .build()
+                .unwrap()
+                .run(&mut io::empty(), &mut output)
+                .unwrap();
+            output
+        };
+
+        assert_eq!(vec!(200, b'A', b'A', b'\n'), run(OutputMode::Raw));
+        assert_eq!(b"HAA\n".to_vec(), run(OutputMode::Mask));
+        assert_eq!(b"\\xc8AA\n".to_vec(), run(OutputMode::Escape));
+    }
+
+    #[test]
+    fn escape_repeated_output() {
+        let mut output = Vec::new();
+        BrainfuckBuilder::new("-...")
+            .output_mode(OutputMode::Escape)
+            .build()
+            .unwrap()
+            .run(&mut io::empty(), &mut output)
+            .unwrap();
+
+        assert_eq!(b"\\xff\\xff\\xff".to_vec(), output);
     }
 }
@@ -1,6 +1,28 @@
+mod builder;
+mod bytecode;
+mod dot;
 mod instruction;
 mod interpreter;
 mod optimizer;
 mod parser;
 
-pub use interpreter::{Brainfuck, Error};
+pub use builder::BrainfuckBuilder;
+pub use bytecode::{decode, encode, InvalidBytecode};
+pub use dot::to_dot;
+pub use instruction::{assemble, disassemble, Instruction, InvalidInstruction};
+pub use interpreter::{
+    compile, eval, run_cases, BoundaryPolicy, Brainfuck, CellArith, CellFormat,
+    CompiledProgram, Error, ExtHandler, FlushMode, OutputIter, OutputMode,
+    PointerMode, Result, RunState, Snapshot,
+};
+pub use optimizer::{
+    flatten, loop_net_shift, nest, optimize, optimize_for, optimize_spanned,
+    optimize_spanned_for, optimize_with, stats, Node, OptLevel, Semantics,
+    Spanned,
+};
+pub use parser::{
+    extract_comments, parse, parse_checked, parse_ook, parse_reader,
+    parse_spanned, parse_with_dialect, parse_with_extensions,
+    parse_with_warnings, parse_words, strip_shebang, Dialect, LineIndex,
+    ParseError, Position, Warning, WarningKind, WordDialect,
+};
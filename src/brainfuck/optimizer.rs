@@ -1,63 +1,520 @@
-use crate::instruction::Instruction;
-use std::collections::VecDeque;
+use crate::{
+    instruction::Instruction,
+    interpreter::{BoundaryPolicy, CellArith, PointerMode},
+};
+use std::{cmp, collections::VecDeque, ops::Range};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum OptLevel {
+    None,
+    #[default]
+    Full,
+}
+
+/// The settings the optimized program will run under, so that passes which
+/// only hold for some of them can stay out of the way.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub struct Semantics {
+    pub cell_arith: CellArith,
+    pub pointer_mode: PointerMode,
+    pub boundary_policy: BoundaryPolicy,
+}
+
+impl Semantics {
+    /// Whether a move by `offset` always lands `offset` cells away, instead of
+    /// stopping at (or failing on) an end of the tape.
+    fn moves_freely(self, offset: isize) -> bool {
+        match (self.pointer_mode, self.boundary_policy) {
+            (PointerMode::Wrap, _) => true,
+            (_, BoundaryPolicy::GrowBoth) => true,
+            (_, BoundaryPolicy::Grow) => offset >= 0,
+            _ => false,
+        }
+    }
+}
+
+/// An instruction stream with its loops nested: each `Open`/`Close` pair
+/// becomes a `Loop` holding the body between them.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Node {
+    Op(Instruction),
+    Loop(Vec<Node>),
+}
+
+/// An instruction with the byte range of the source it came from.
+pub type Spanned = (Instruction, Range<usize>);
 
 pub fn optimize(instructions: VecDeque<Instruction>) -> VecDeque<Instruction> {
-    compact_binary(instructions)
+    optimize_for(instructions, Semantics::default())
 }
 
-fn compact_binary(
-    mut instructions: VecDeque<Instruction>,
+/// Optimizes like `optimize`, for a program that will run under `semantics`.
+pub fn optimize_for(
+    instructions: VecDeque<Instruction>,
+    semantics: Semantics,
 ) -> VecDeque<Instruction> {
+    let spanned = instructions.into_iter().map(|i| (i, 0..0)).collect();
+
+    optimize_spanned_for(spanned, semantics)
+        .into_iter()
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Optimizes like `optimize`, giving every instruction it emits the span of
+/// all the instructions it replaced, e.g. `Add(3)` spans the whole of `+++`.
+pub fn optimize_spanned(instructions: VecDeque<Spanned>) -> VecDeque<Spanned> {
+    optimize_spanned_for(instructions, Semantics::default())
+}
+
+pub fn optimize_spanned_for(
+    instructions: VecDeque<Spanned>,
+    semantics: Semantics,
+) -> VecDeque<Spanned> {
+    let compacted = compact_binary(instructions, semantics);
+    let lowered = lower_sets(drop_dead_loops(lower_moves(compacted, semantics)));
+
+    collapse_pointer_moves(lowered, semantics)
+}
+
+pub fn optimize_with(
+    instructions: VecDeque<Instruction>,
+    level: OptLevel,
+    semantics: Semantics,
+) -> VecDeque<Instruction> {
+    match level {
+        OptLevel::None => instructions,
+        OptLevel::Full => optimize_for(instructions, semantics),
+    }
+}
+
+/// Fraction of instructions removed when going from `before` instructions to
+/// `after`, e.g. `0.75` when four instructions were folded into one.
+pub fn stats(before: usize, after: usize) -> f64 {
+    if before == 0 {
+        return 0.0;
+    }
+
+    1.0 - after as f64 / before as f64
+}
+
+/// How far one iteration of a loop with this `body` moves the data pointer, or
+/// `None` if that depends on how often a nested loop runs, i.e. a nested loop
+/// has a shift of its own, or if the body's brackets don't balance.
+pub fn loop_net_shift(body: &[Instruction]) -> Option<isize> {
+    let mut shifts = vec![0isize];
+
+    for instruction in body {
+        match *instruction {
+            Instruction::Right(n) => *shifts.last_mut()? += n as isize,
+            Instruction::Left(n) => *shifts.last_mut()? -= n as isize,
+            Instruction::Open => shifts.push(0),
+            Instruction::Close => {
+                let inner = shifts.pop()?;
+
+                if inner != 0 || shifts.is_empty() {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if shifts.len() == 1 {
+        shifts.pop()
+    } else {
+        None
+    }
+}
+
+/// Nests the loops of a flat stream, or `None` if its brackets don't balance.
+pub fn nest(instructions: &[Instruction]) -> Option<Vec<Node>> {
+    let mut levels = vec![Vec::new()];
+
+    for &instruction in instructions {
+        match instruction {
+            Instruction::Open => levels.push(Vec::new()),
+            Instruction::Close => {
+                let body = levels.pop()?;
+                levels.last_mut()?.push(Node::Loop(body));
+            }
+            instruction => levels.last_mut()?.push(Node::Op(instruction)),
+        }
+    }
+
+    match levels.len() {
+        1 => levels.pop(),
+        _ => None,
+    }
+}
+
+/// Turns nested loops back into a flat stream of `Open`/`Close` pairs.
+pub fn flatten(nodes: &[Node]) -> Vec<Instruction> {
+    fn push(flat: &mut Vec<Instruction>, nodes: &[Node]) {
+        for node in nodes {
+            match node {
+                Node::Op(instruction) => flat.push(*instruction),
+                Node::Loop(body) => {
+                    flat.push(Instruction::Open);
+                    push(flat, body);
+                    flat.push(Instruction::Close);
+                }
+            }
+        }
+    }
+
+    let mut flat = Vec::with_capacity(nodes.len());
+    push(&mut flat, nodes);
+    flat
+}
+
+pub(crate) fn link(instructions: &[Instruction]) -> Vec<usize> {
+    let mut jumps = vec![instructions.len(); instructions.len()];
+    let mut opens = Vec::new();
+
+    for (ip, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::Open => opens.push(ip),
+            Instruction::Close => {
+                if let Some(open) = opens.pop() {
+                    jumps[open] = ip;
+                    jumps[ip] = open;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    jumps
+}
+
+/// Merges runs of the same command. `+` and `-` next to each other are
+/// folded into their net effect only with wrapping cells: a saturated cell
+/// doesn't come back from `-+` where it started.
+fn compact_binary(
+    instructions: VecDeque<Spanned>,
+    semantics: Semantics,
+) -> VecDeque<Spanned> {
     use Instruction::*;
 
-    if instructions.len() < 2 {
-        return instructions;
+    let wrapping = semantics.cell_arith == CellArith::Wrap;
+    // Anywhere but on a wrapping tape, `><` can stop at, fail on or grow the
+    // tape's edge, so only there is it a no-op.
+    let round_trips = semantics.pointer_mode == PointerMode::Wrap;
+
+    let mut compacted = VecDeque::with_capacity(instructions.len());
+
+    for (b, b_span) in instructions {
+        let (a, a_span) = match compacted.pop_back() {
+            Some(a) => a,
+            None => {
+                compacted.push_back((b, b_span));
+                continue;
+            }
+        };
+        let span = a_span.start..b_span.end;
+
+        match (a, b) {
+            // Splitting at 255 rather than wrapping keeps saturating cells
+            // correct too.
+            (Add(x), Add(y)) => match x.checked_add(y) {
+                Some(sum) => compacted.push_back((Add(sum), span)),
+                None => compacted.extend(vec![
+                    (Add(255), a_span),
+                    (Add(x - (255 - y)), b_span),
+                ]),
+            },
+            (Sub(x), Sub(y)) => match x.checked_add(y) {
+                Some(sum) => compacted.push_back((Sub(sum), span)),
+                None => compacted.extend(vec![
+                    (Sub(255), a_span),
+                    (Sub(x - (255 - y)), b_span),
+                ]),
+            },
+            (Right(x), Right(y)) => compacted.push_back((Right(x + y), span)),
+            (Left(x), Left(y)) => compacted.push_back((Left(x + y), span)),
+            (Out, Out) => compacted.push_back((OutN(2), span)),
+            (OutN(x), Out) => compacted.push_back((OutN(x + 1), span)),
+            (Add(x), Sub(y)) | (Sub(y), Add(x)) if wrapping && x > y => {
+                compacted.push_back((Add(x - y), span))
+            }
+            (Add(x), Sub(y)) | (Sub(y), Add(x)) if wrapping && x < y => {
+                compacted.push_back((Sub(y - x), span))
+            }
+            (Add(_), Sub(_)) | (Sub(_), Add(_)) if wrapping => {}
+            (Right(x), Left(y)) | (Left(x), Right(y))
+                if round_trips && x == y => {}
+            _ => {
+                compacted.push_back((a, a_span));
+                compacted.push_back((b, b_span));
+            }
+        }
     }
 
-    let a = instructions.pop_front().unwrap();
-    let b = instructions.pop_front().unwrap();
+    compacted
+}
+
+/// Lowers transfer loops like `[->+<]` to `Move`. The interpreter runs a
+/// `Move` that would reach past an end of the tape as the loop it came from,
+/// subtracting first. Loops that subtract last, like `[>+<-]`, only match that
+/// when their pointer can't be stopped at an end.
+fn lower_moves(
+    instructions: VecDeque<Spanned>,
+    semantics: Semantics,
+) -> VecDeque<Spanned> {
+    use Instruction::*;
+
+    let mut lowered: VecDeque<Spanned> =
+        VecDeque::with_capacity(instructions.len());
 
-    match (a, b) {
-        (Add(x), Add(y)) => {
-            instructions.push_front(Add(x + y));
-            compact_binary(instructions)
+    for (i, span) in instructions {
+        lowered.push_back((i, span));
+
+        if i != Close || lowered.len() < 6 {
+            continue;
         }
-        (Sub(x), Sub(y)) => {
-            instructions.push_front(Sub(x + y));
-            compact_binary(instructions)
+
+        let start = lowered.len() - 6;
+        let window = lowered.range(start..).map(|s| s.0).collect::<Vec<_>>();
+        let (offset, sub_first) = match window[..] {
+            [Open, Sub(1), Right(x), Add(1), Left(_), Close] => {
+                (x as isize, true)
+            }
+            [Open, Right(x), Add(1), Left(_), Sub(1), Close] => {
+                (x as isize, false)
+            }
+            [Open, Sub(1), Left(x), Add(1), Right(_), Close] => {
+                (-(x as isize), true)
+            }
+            [Open, Left(x), Add(1), Right(_), Sub(1), Close] => {
+                (-(x as isize), false)
+            }
+            _ => continue,
+        };
+
+        if loop_net_shift(&window[1..5]) != Some(0) {
+            continue;
         }
-        (Right(x), Right(y)) => {
-            instructions.push_front(Right(x + y));
-            compact_binary(instructions)
+
+        if !sub_first && !semantics.moves_freely(offset) {
+            continue;
         }
-        (Left(x), Left(y)) => {
-            instructions.push_front(Left(x + y));
-            compact_binary(instructions)
+
+        let span = lowered[start].1.start..lowered[start + 5].1.end;
+        lowered.truncate(start);
+        lowered.push_back((Move { offset }, span));
+    }
+
+    lowered
+}
+
+/// Lowers `[-]` to `Clear` and removes loops that directly follow a `Clear` or
+/// a `Move`, since both leave the current cell at zero. `[+]` is left alone as
+/// it never terminates on a saturated cell. A dead loop whose `]` never
+/// comes is kept, so that linking still reports it.
+fn drop_dead_loops(instructions: VecDeque<Spanned>) -> VecDeque<Spanned> {
+    use Instruction::*;
+
+    let mut kept: VecDeque<Spanned> = VecDeque::with_capacity(instructions.len());
+    let mut dead: Vec<Spanned> = Vec::new();
+    let mut dead_depth = 0;
+
+    for (i, span) in instructions {
+        if dead_depth > 0 {
+            match i {
+                Open => dead_depth += 1,
+                Close => dead_depth -= 1,
+                _ => {}
+            }
+
+            dead.push((i, span));
+            if dead_depth == 0 {
+                dead.clear();
+            }
+            continue;
         }
-        (Add(x), Sub(y)) | (Sub(x), Add(y)) if x == y => {
-            compact_binary(instructions)
+
+        if i == Open {
+            if let Some((Clear, _)) | Some((Move { .. }, _)) = kept.back() {
+                dead_depth = 1;
+                dead.push((i, span));
+                continue;
+            }
         }
-        (Right(x), Left(y)) | (Left(x), Right(y)) if x == y => {
-            compact_binary(instructions)
+
+        kept.push_back((i, span));
+
+        let start = match kept.len().checked_sub(3) {
+            Some(start) if i == Close => start,
+            _ => continue,
+        };
+
+        if kept
+            .range(start..)
+            .map(|s| s.0)
+            .eq(vec![Open, Sub(1), Close])
+        {
+            let span = kept[start].1.start..kept[start + 2].1.end;
+            kept.truncate(start);
+            kept.push_back((Clear, span));
         }
-        _ => {
-            instructions.push_front(b);
-            let mut rest = compact_binary(instructions);
-            rest.push_front(a);
-            rest
+    }
+
+    kept.extend(dead);
+    kept
+}
+
+/// Fuses `Clear, Add(n)` into `Set(n)`. A following `Sub` is left alone, as
+/// it wraps or saturates depending on the cell arithmetic.
+fn lower_sets(instructions: VecDeque<Spanned>) -> VecDeque<Spanned> {
+    use Instruction::*;
+
+    let mut lowered: VecDeque<Spanned> =
+        VecDeque::with_capacity(instructions.len());
+
+    for (i, span) in instructions {
+        match (lowered.back(), i) {
+            (Some((Clear, clear)), Add(n)) => {
+                let span = clear.start..span.end;
+                lowered.pop_back();
+                lowered.push_back((Set(n), span));
+            }
+            _ => lowered.push_back((i, span)),
         }
     }
+
+    lowered
+}
+
+/// Within each loop-free run of `Add`, `Sub`, `Right` and `Left`, turns the
+/// arithmetic into offset operations and moves the pointer once at the end,
+/// e.g. `+>+>>-<` becomes `Add(1), AddOffset(1), SubOffset(3), Right(2)`.
+///
+/// A run is only collapsed where its moves can't be stopped short by an end
+/// of the tape: on a wrapping tape, or on a growing one that the run never
+/// leaves to the left of where it started. Elsewhere a clamped pointer would
+/// shift every cell after it, so the real moves are kept.
+fn collapse_pointer_moves(
+    instructions: VecDeque<Spanned>,
+    semantics: Semantics,
+) -> VecDeque<Spanned> {
+    use Instruction::*;
+
+    let mut collapsed = VecDeque::with_capacity(instructions.len());
+    let mut block = Vec::new();
+
+    for (i, span) in instructions {
+        match i {
+            Add(_) | Sub(_) | Right(_) | Left(_) => block.push((i, span)),
+            _ => {
+                collapse_block(&mut collapsed, block.drain(..), semantics);
+                collapsed.push_back((i, span));
+            }
+        }
+    }
+
+    collapse_block(&mut collapsed, block.drain(..), semantics);
+
+    collapsed
+}
+
+fn collapse_block<I>(
+    collapsed: &mut VecDeque<Spanned>,
+    block: I,
+    semantics: Semantics,
+) where
+    I: Iterator<Item = Spanned>,
+{
+    use Instruction::*;
+
+    let mut block = block.peekable();
+
+    // Leading moves stay real moves, so the pointer still stops wherever the
+    // tape's edge would stop it.
+    while let Some((Right(_), _)) | Some((Left(_), _)) = block.peek() {
+        collapsed.extend(block.next());
+    }
+
+    let block: Vec<_> = block.collect();
+    let moves = block
+        .iter()
+        .filter(|(i, _)| matches!(i, Right(_) | Left(_)))
+        .count();
+
+    if moves < 2 || !collapses(&block, semantics) {
+        collapsed.extend(block);
+        return;
+    }
+
+    let span = block[0].1.start..block[block.len() - 1].1.end;
+    let mut delta = 0isize;
+
+    for (i, span) in block {
+        let i = match i {
+            Right(n) => {
+                delta += n as isize;
+                continue;
+            }
+            Left(n) => {
+                delta -= n as isize;
+                continue;
+            }
+            Add(n) if delta != 0 => AddOffset { offset: delta, n },
+            Sub(n) if delta != 0 => SubOffset { offset: delta, n },
+            i => i,
+        };
+
+        collapsed.push_back((i, span));
+    }
+
+    match delta {
+        0 => {}
+        delta if delta > 0 => collapsed.push_back((Right(delta as usize), span)),
+        delta => collapsed.push_back((Left(delta.unsigned_abs()), span)),
+    }
+}
+
+/// Whether the run in `block` lands on the same cells, and stretches the tape
+/// as far, with its moves folded into offsets.
+fn collapses(block: &[Spanned], semantics: Semantics) -> bool {
+    use Instruction::*;
+
+    match (semantics.pointer_mode, semantics.boundary_policy) {
+        (PointerMode::Wrap, _) => return true,
+        (_, BoundaryPolicy::Grow) | (_, BoundaryPolicy::GrowBoth) => {}
+        _ => return false,
+    }
+
+    let mut delta = 0isize;
+    let mut lowest = 0;
+    let mut furthest = 0;
+    let mut reached = 0;
+
+    for (i, _) in block {
+        match *i {
+            Right(n) => delta += n as isize,
+            Left(n) => delta -= n as isize,
+            _ => reached = cmp::max(reached, delta),
+        }
+
+        lowest = cmp::min(lowest, delta);
+        furthest = cmp::max(furthest, delta);
+    }
+
+    // Growing to the left, or only partway to the right, would size the tape
+    // differently than the moves do.
+    lowest >= 0 && cmp::max(reached, delta) == furthest
 }
 
 #[cfg(test)]
 mod test {
+    use super::{OptLevel, Semantics};
     use crate::instruction::Instruction::{self, *};
+    use crate::interpreter::BoundaryPolicy;
     use std::{collections::VecDeque, iter::FromIterator};
 
     fn optimize(vec: Vec<Instruction>) -> Vec<Instruction> {
-        Vec::from_iter(
-            super::optimize(VecDeque::from_iter(vec.into_iter())).into_iter(),
-        )
+        Vec::from_iter(super::optimize(VecDeque::from_iter(vec)))
     }
 
     #[test]
@@ -70,6 +527,325 @@ mod test {
         );
     }
 
+    #[test]
+    fn compact_past_255() {
+        use crate::parser::parse;
+
+        let optimize =
+            |src: String| Vec::from(super::optimize(parse(src.as_bytes())));
+
+        assert_eq!(vec!(Add(255), Add(45)), optimize("+".repeat(300)));
+        assert_eq!(vec!(Sub(255), Sub(255), Sub(2)), optimize("-".repeat(512)));
+        assert_eq!(vec!(Add(255)), optimize("+".repeat(255)));
+    }
+
+    #[test]
+    fn compact_net_add_sub() {
+        assert_eq!(vec!(Add(3)), optimize(vec!(Add(5), Sub(2))));
+        assert_eq!(vec!(Sub(2)), optimize(vec!(Add(1), Sub(3))));
+        assert_eq!(vec!(Add(1)), optimize(vec!(Sub(2), Add(3))));
+        assert_eq!(vec!(Sub(4)), optimize(vec!(Sub(5), Add(1))));
+        assert_eq!(Vec::<Instruction>::new(), optimize(vec!(Add(2), Sub(2))));
+    }
+
+    #[test]
+    fn parsed_net_add_sub() {
+        use crate::parser::parse;
+
+        assert_eq!(vec!(Add(3)), Vec::from(super::optimize(parse(b"+++++--"))));
+        assert!(super::optimize(parse(b"++--")).is_empty());
+    }
+
+    #[test]
+    fn lower_moves() {
+        assert_eq!(
+            vec!(Add(2), Move { offset: 1 }),
+            optimize(vec!(
+                Add(2),
+                Open,
+                Sub(1),
+                Right(1),
+                Add(1),
+                Left(1),
+                Close
+            ))
+        );
+        assert_eq!(
+            vec!(Move { offset: -3 }),
+            optimize(vec!(Open, Sub(1), Left(3), Add(1), Right(3), Close))
+        );
+        assert_eq!(
+            vec!(Open, Left(3), Add(1), Right(3), Sub(1), Close),
+            optimize(vec!(Open, Left(3), Add(1), Right(3), Sub(1), Close))
+        );
+
+        let growing = Semantics {
+            boundary_policy: BoundaryPolicy::GrowBoth,
+            ..Semantics::default()
+        };
+
+        assert_eq!(
+            vec!(Move { offset: -3 }),
+            Vec::from(super::optimize_for(
+                VecDeque::from(vec!(
+                    Open,
+                    Left(3),
+                    Add(1),
+                    Right(3),
+                    Sub(1),
+                    Close
+                )),
+                growing
+            ))
+        );
+        assert_eq!(
+            vec!(Open, Sub(1), Right(1), Add(2), Left(1), Close),
+            optimize(vec!(Open, Sub(1), Right(1), Add(2), Left(1), Close))
+        );
+        assert_eq!(
+            vec!(Open, Sub(1), Right(2), Add(1), Left(1), Close),
+            optimize(vec!(Open, Sub(1), Right(2), Add(1), Left(1), Close))
+        );
+    }
+
+    #[test]
+    fn drop_dead_loops() {
+        use crate::parser::parse;
+
+        let optimize = |src: &[u8]| Vec::from(super::optimize(parse(src)));
+
+        assert_eq!(vec!(Clear), optimize(b"[-][+]"));
+        assert_eq!(vec!(Add(1), Clear, Out), optimize(b"+[-][>[<]]."));
+        assert_eq!(vec!(Move { offset: 1 }), optimize(b"[->+<][-]"));
+        assert_eq!(vec!(Open, Add(1), Close, Clear), optimize(b"[+][-]"));
+        assert_eq!(vec!(Add(1), Clear), optimize(b"+[-]"));
+        assert_eq!(
+            vec!(Clear, Open, Add(1), Out, Right(1)),
+            optimize(b"[-][+.>")
+        );
+    }
+
+    #[test]
+    fn spans() {
+        use crate::parser::parse_spanned;
+
+        let spans = |src: &[u8]| {
+            super::optimize_spanned(parse_spanned(src))
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec!((Add(3), 0..3), (Right(2), 4..7), (OutN(2), 7..9)),
+            spans(b"+++ >\n>..")
+        );
+        assert_eq!(
+            vec!((Add(2), 0..2), (Move { offset: 1 }, 2..8), (Out, 11..12)),
+            spans(b"++[->+<][-].")
+        );
+        assert_eq!(vec!((Clear, 1..6)), spans(b" [ - ] "));
+        assert_eq!(
+            vec!((Add(255), 0..255), (Add(1), 255..256)),
+            spans("+".repeat(256).as_bytes())
+        );
+    }
+
+    #[test]
+    fn lower_sets() {
+        use crate::parser::parse;
+
+        let optimize = |src: &[u8]| Vec::from(super::optimize(parse(src)));
+
+        assert_eq!(vec!(Set(5)), optimize(b"[-]+++++"));
+        assert_eq!(vec!(Right(1), Set(2), Out), optimize(b">[-][+]++."));
+        assert_eq!(vec!(Clear, Sub(1)), optimize(b"[-]-"));
+        assert_eq!(vec!(Clear, Right(1), Add(1)), optimize(b"[-]>+"));
+        assert_eq!(vec!(Open, Add(1), Close, Add(3)), optimize(b"[+]+++"));
+    }
+
+    #[test]
+    fn collapse_pointer_moves() {
+        use crate::interpreter::PointerMode;
+
+        let optimize_for = |vec: Vec<Instruction>, semantics| {
+            Vec::from(super::optimize_for(VecDeque::from(vec), semantics))
+        };
+        let growing = Semantics {
+            boundary_policy: BoundaryPolicy::Grow,
+            ..Semantics::default()
+        };
+        let wrapping = Semantics {
+            pointer_mode: PointerMode::Wrap,
+            ..Semantics::default()
+        };
+
+        assert_eq!(
+            vec!(
+                Add(1),
+                AddOffset { offset: 1, n: 1 },
+                SubOffset { offset: 3, n: 1 },
+                Right(2)
+            ),
+            optimize_for(
+                vec!(Add(1), Right(1), Add(1), Right(2), Sub(1), Left(1)),
+                growing
+            )
+        );
+        assert_eq!(
+            vec!(
+                Open,
+                Sub(1),
+                AddOffset { offset: 1, n: 2 },
+                AddOffset { offset: 2, n: 1 },
+                Close
+            ),
+            optimize_for(
+                vec!(
+                    Open,
+                    Sub(1),
+                    Right(1),
+                    Add(2),
+                    Right(1),
+                    Add(1),
+                    Left(2),
+                    Close
+                ),
+                growing
+            )
+        );
+        assert_eq!(
+            vec!(Add(1), Right(1), Add(1), Out),
+            optimize_for(vec!(Add(1), Right(1), Add(1), Out), growing)
+        );
+        assert_eq!(
+            vec!(Add(1), Left(1), Add(1), Left(1), Add(1), Right(2)),
+            optimize_for(
+                vec!(Add(1), Left(1), Add(1), Left(1), Add(1), Right(2)),
+                growing
+            )
+        );
+        assert_eq!(
+            vec!(
+                Add(1),
+                AddOffset { offset: -1, n: 1 },
+                AddOffset { offset: -2, n: 1 },
+                Left(2)
+            ),
+            optimize_for(
+                vec!(Add(1), Left(1), Add(1), Left(1), Add(1)),
+                wrapping
+            )
+        );
+        assert_eq!(
+            vec!(Add(1), Right(1), Add(1), Right(2), Sub(1), Left(1)),
+            optimize(vec!(Add(1), Right(1), Add(1), Right(2), Sub(1), Left(1)))
+        );
+    }
+
+    #[test]
+    fn collapsed_blocks_run_the_same() {
+        use crate::{
+            builder::BrainfuckBuilder,
+            interpreter::{Brainfuck, PointerMode},
+        };
+        use std::io;
+
+        let build = |program, dp, mode, policy, optimize| {
+            BrainfuckBuilder::new(program)
+                .initial_dp(dp)
+                .pointer_mode(mode)
+                .boundary_policy(policy)
+                .optimize(optimize)
+                .build()
+                .unwrap()
+        };
+        let run = |mut brainfuck: Brainfuck| {
+            let mut output = Vec::new();
+            let result = brainfuck.run(&mut io::empty(), &mut output);
+
+            (result, output, brainfuck)
+        };
+
+        for &(program, dp, mode, policy) in &[
+            (
+                ">+>>-<.<<+++.",
+                0,
+                PointerMode::Clamp,
+                BoundaryPolicy::Clamp,
+            ),
+            (
+                "++++[>++>+++>+<<<-]>>>[<+>>+<-]<.>>.",
+                0,
+                PointerMode::Clamp,
+                BoundaryPolicy::Grow,
+            ),
+            (
+                ">>>+<<-<+>>>.>+<<<.",
+                0,
+                PointerMode::Clamp,
+                BoundaryPolicy::Grow,
+            ),
+            (
+                "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---\
+                 .+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.\n",
+                0,
+                PointerMode::Clamp,
+                BoundaryPolicy::Grow,
+            ),
+            ("+<+<+>>.", 0, PointerMode::Clamp, BoundaryPolicy::Clamp),
+            (">+<<+>>>+<<<", 0, PointerMode::Clamp, BoundaryPolicy::Clamp),
+            ("+>+<.", 29_999, PointerMode::Clamp, BoundaryPolicy::Clamp),
+            ("+>+<.", 29_999, PointerMode::Clamp, BoundaryPolicy::Error),
+            ("+<+<+>>.", 0, PointerMode::Clamp, BoundaryPolicy::Grow),
+            (">>>+<-<.", 29_998, PointerMode::Clamp, BoundaryPolicy::Grow),
+            ("+<+>>+<.", 0, PointerMode::Clamp, BoundaryPolicy::GrowBoth),
+            ("+<+<+>>.", 0, PointerMode::Wrap, BoundaryPolicy::Clamp),
+            ("+>+>+<<.", 29_999, PointerMode::Wrap, BoundaryPolicy::Clamp),
+        ] {
+            let (plain_result, plain_output, plain) =
+                run(build(program, dp, mode, policy, false));
+            let (result, output, collapsed) =
+                run(build(program, dp, mode, policy, true));
+
+            assert_eq!(plain_result, result, "{}", program);
+            assert_eq!(plain_output, output, "{}", program);
+            assert_eq!(plain.tape_len(), collapsed.tape_len(), "{}", program);
+            assert_eq!(
+                plain.tape(0..plain.tape_len()),
+                collapsed.tape(0..collapsed.tape_len()),
+                "{}",
+                program
+            );
+            assert_eq!(plain.tape_pointer(), collapsed.tape_pointer());
+        }
+    }
+
+    #[test]
+    fn stats() {
+        assert_eq!(0.75, super::stats(4, 1));
+        assert_eq!(0.0, super::stats(3, 3));
+        assert_eq!(0.0, super::stats(0, 0));
+    }
+
+    #[test]
+    fn loop_net_shift() {
+        use super::loop_net_shift;
+        use crate::parser::parse;
+
+        let shift = |body: &[u8]| loop_net_shift(&Vec::from(parse(body)));
+
+        assert_eq!(Some(0), shift(b"->+<"));
+        assert_eq!(Some(2), shift(b"->+>"));
+        assert_eq!(Some(1), shift(b"->+"));
+        assert_eq!(Some(-3), shift(b"<<[-]<"));
+        assert_eq!(Some(0), shift(b">[->+<]<"));
+        assert_eq!(None, shift(b"<<[>]<"));
+        assert_eq!(None, shift(b"-]"));
+        assert_eq!(None, shift(b"[-"));
+        assert_eq!(Some(0), shift(b""));
+        assert_eq!(Some(4), loop_net_shift(&[Right(3), Add(2), Right(1)]));
+    }
+
     #[test]
     fn compact_sub() {
         assert_eq!(vec!(Sub(1)), optimize(vec!(Sub(1))));
@@ -97,6 +873,99 @@ mod test {
 
     #[test]
     fn compact_right_left() {
-        assert_eq!(Vec::<Instruction>::new(), optimize(vec!(Right(5), Left(5))));
+        use crate::interpreter::PointerMode;
+
+        let wrapping = Semantics {
+            pointer_mode: PointerMode::Wrap,
+            ..Semantics::default()
+        };
+
+        assert_eq!(
+            Vec::<Instruction>::new(),
+            Vec::from(super::optimize_for(
+                VecDeque::from(vec!(Right(5), Left(5))),
+                wrapping
+            ))
+        );
+        assert_eq!(vec!(Right(5), Left(5)), optimize(vec!(Right(5), Left(5))));
+    }
+
+    #[test]
+    fn compact_long_program() {
+        assert_eq!(vec!(Right(100_000)), optimize(vec!(Right(1); 100_000)));
+    }
+
+    #[test]
+    fn opt_level_none() {
+        let instructions =
+            VecDeque::from(vec![Add(1), Add(1), Right(1), Left(1)]);
+
+        assert_eq!(
+            instructions,
+            super::optimize_with(
+                instructions.clone(),
+                OptLevel::None,
+                Semantics::default(),
+            )
+        );
+        assert_eq!(
+            VecDeque::from(vec![Add(2), Right(1), Left(1)]),
+            super::optimize_with(
+                instructions,
+                OptLevel::Full,
+                Semantics::default(),
+            )
+        );
+    }
+
+    #[test]
+    fn link() {
+        assert_eq!(
+            vec!(5, 2, 1, 4, 3, 0, 8, 8),
+            super::link(&[Open, Open, Close, Open, Close, Close, Out, Open])
+        );
+    }
+
+    #[test]
+    fn nest() {
+        use super::Node::{Loop, Op};
+
+        let flat = vec![Add(1), Open, Sub(1), Open, Close, Right(1), Close, Out];
+        let nodes = vec![
+            Op(Add(1)),
+            Loop(vec![Op(Sub(1)), Loop(vec![]), Op(Right(1))]),
+            Op(Out),
+        ];
+
+        assert_eq!(Some(nodes.clone()), super::nest(&flat));
+        assert_eq!(flat, super::flatten(&nodes));
+        assert_eq!(None, super::nest(&[Open, Add(1)]));
+        assert_eq!(None, super::nest(&[Close, Open]));
+    }
+
+    #[test]
+    fn compact_saturating() {
+        use crate::interpreter::CellArith;
+
+        let saturating = Semantics {
+            cell_arith: CellArith::Saturate,
+            ..Semantics::default()
+        };
+        let optimize =
+            |vec| Vec::from(super::optimize_for(VecDeque::from(vec), saturating));
+
+        assert_eq!(vec!(Sub(1), Add(1)), optimize(vec!(Sub(1), Add(1))));
+        assert_eq!(vec!(Add(2), Sub(3)), optimize(vec!(Add(2), Sub(1), Sub(2))));
+        assert_eq!(vec!(Add(255), Add(45)), optimize(vec!(Add(200), Add(100))));
+    }
+
+    #[test]
+    fn compact_out() {
+        assert_eq!(vec!(Out), optimize(vec!(Out)));
+        assert_eq!(vec!(OutN(4)), optimize(vec!(Out, Out, Out, Out)));
+        assert_eq!(
+            vec!(OutN(2), Add(1), Out, Right(1), OutN(2)),
+            optimize(vec!(Out, Out, Add(1), Out, Right(1), Out, Out))
+        );
     }
 }
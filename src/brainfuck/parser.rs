@@ -1,8 +1,201 @@
 use crate::instruction::Instruction;
-use std::collections::VecDeque;
+use std::{
+    cmp,
+    collections::VecDeque,
+    io::{self, Read},
+    ops::Range,
+};
 
+const READ_CHUNK: usize = 8 * 1024;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum WarningKind {
+    UnmatchedOpen,
+    UnmatchedClose,
+    EmptyLoop,
+    LeadingLoop,
+    NoCommands,
+    /// More than 255 `+` or `-` in a row, which wraps the cell around.
+    WrappingRun,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub position: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// Finds the 1-based line and column of the byte at `offset` in `src`.
+    pub fn locate(src: &[u8], offset: usize) -> Position {
+        LineIndex::new(src).locate(offset)
+    }
+}
+
+/// Byte offsets of every line start in a source, built once so that many
+/// offsets can be turned into positions without rescanning the source.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    /// `\n`, `\r\n` and a lone `\r` all count as a single line break.
+    pub fn new(src: &[u8]) -> LineIndex {
+        let newlines = src
+            .iter()
+            .enumerate()
+            .filter(|&(i, &b)| match b {
+                b'\n' => true,
+                b'\r' => src.get(i + 1) != Some(&b'\n'),
+                _ => false,
+            })
+            .map(|(i, _)| i + 1);
+
+        LineIndex {
+            starts: std::iter::once(0).chain(newlines).collect(),
+            len: src.len(),
+        }
+    }
+
+    pub fn locate(&self, offset: usize) -> Position {
+        let offset = cmp::min(offset, self.len);
+        let line = match self.starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+
+        Position {
+            line: line + 1,
+            column: offset - self.starts[line] + 1,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ParseError {
+    UnmatchedOpen(usize),
+    UnmatchedClose(usize),
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Dialect {
+    pub add: char,
+    pub sub: char,
+    pub right: char,
+    pub left: char,
+    pub output: char,
+    pub input: char,
+    pub open: char,
+    pub close: char,
+    pub out_decimal: Option<char>,
+    pub out_unicode: Option<char>,
+    pub swap: Option<char>,
+}
+
+impl Default for Dialect {
+    fn default() -> Dialect {
+        Dialect {
+            add: '+',
+            sub: '-',
+            right: '>',
+            left: '<',
+            output: '.',
+            input: ',',
+            open: '[',
+            close: ']',
+            out_decimal: None,
+            out_unicode: None,
+            swap: None,
+        }
+    }
+}
+
+impl Dialect {
+    fn parse_char(&self, c: char) -> Option<Instruction> {
+        match c {
+            _ if c == self.add => Some(Instruction::Add(1)),
+            _ if c == self.sub => Some(Instruction::Sub(1)),
+            _ if c == self.right => Some(Instruction::Right(1)),
+            _ if c == self.left => Some(Instruction::Left(1)),
+            _ if c == self.output => Some(Instruction::Out),
+            _ if c == self.input => Some(Instruction::In),
+            _ if c == self.open => Some(Instruction::Open),
+            _ if c == self.close => Some(Instruction::Close),
+            _ if Some(c) == self.out_decimal => Some(Instruction::OutDecimal),
+            _ if Some(c) == self.out_unicode => Some(Instruction::OutUnicode),
+            _ if Some(c) == self.swap => Some(Instruction::Swap),
+            _ => None,
+        }
+    }
+}
+
+/// Spells each command as a whole word, e.g. `incr` for `+`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct WordDialect {
+    pub add: String,
+    pub sub: String,
+    pub right: String,
+    pub left: String,
+    pub output: String,
+    pub input: String,
+    pub open: String,
+    pub close: String,
+}
+
+impl Default for WordDialect {
+    fn default() -> WordDialect {
+        WordDialect {
+            add: String::from("incr"),
+            sub: String::from("decr"),
+            right: String::from("next"),
+            left: String::from("prev"),
+            output: String::from("print"),
+            input: String::from("read"),
+            open: String::from("loop"),
+            close: String::from("end"),
+        }
+    }
+}
+
+impl WordDialect {
+    fn parse_word(&self, word: &str) -> Option<Instruction> {
+        match word {
+            _ if word == self.add => Some(Instruction::Add(1)),
+            _ if word == self.sub => Some(Instruction::Sub(1)),
+            _ if word == self.right => Some(Instruction::Right(1)),
+            _ if word == self.left => Some(Instruction::Left(1)),
+            _ if word == self.output => Some(Instruction::Out),
+            _ if word == self.input => Some(Instruction::In),
+            _ if word == self.open => Some(Instruction::Open),
+            _ if word == self.close => Some(Instruction::Close),
+            _ => None,
+        }
+    }
+}
+
+/// Turns brainfuck source into instructions, skipping every non-command byte.
+///
+/// The result can be fed to `optimize` without ever running the program:
+///
+/// ```
+/// use brainfuck::{optimize, parse, Instruction::*};
+///
+/// let instructions = parse(b"+++ add three then move >");
+/// assert_eq!(4, instructions.len());
+///
+/// let optimized = optimize(instructions);
+/// assert_eq!(vec!(Add(3), Right(1)), Vec::from(optimized));
+/// ```
 pub fn parse(bytes: &[u8]) -> VecDeque<Instruction> {
-    let mut instructions = VecDeque::with_capacity(bytes.len());
+    let mut instructions = VecDeque::with_capacity(command_count(bytes));
 
     for b in bytes {
         if let Some(i) = parse_byte(*b) {
@@ -13,6 +206,235 @@ pub fn parse(bytes: &[u8]) -> VecDeque<Instruction> {
     instructions
 }
 
+/// Like `parse`, but pairs each instruction with the byte it was parsed from,
+/// ready for `optimize_spanned`.
+pub fn parse_spanned(bytes: &[u8]) -> VecDeque<(Instruction, Range<usize>)> {
+    bytes
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &b)| {
+            parse_byte(b).map(|instruction| (instruction, i..i + 1))
+        })
+        .collect()
+}
+
+pub fn parse_reader<R: Read>(mut reader: R) -> io::Result<VecDeque<Instruction>> {
+    let mut instructions = VecDeque::new();
+    let mut buffer = [0; READ_CHUNK];
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        instructions.extend(buffer[..read].iter().filter_map(|b| parse_byte(*b)));
+    }
+
+    Ok(instructions)
+}
+
+pub fn parse_with_extensions(
+    bytes: &[u8],
+    extensions: &[u8],
+) -> VecDeque<Instruction> {
+    bytes
+        .iter()
+        .filter_map(|&b| match parse_byte(b) {
+            Some(i) => Some(i),
+            None if b.is_ascii_graphic() && extensions.contains(&b) => {
+                Some(Instruction::Ext(b))
+            }
+            None => None,
+        })
+        .collect()
+}
+
+pub fn parse_with_warnings(
+    bytes: &[u8],
+) -> (VecDeque<Instruction>, Vec<Warning>) {
+    let mut instructions = VecDeque::with_capacity(command_count(bytes));
+    let mut warnings = Vec::new();
+    let mut opens = Vec::new();
+    let mut touched = false;
+    let mut run = None;
+
+    for (position, b) in bytes.iter().enumerate() {
+        let i = match parse_byte(*b) {
+            Some(i) => i,
+            None => continue,
+        };
+
+        match i {
+            Instruction::Open => {
+                let leading = !touched && opens.is_empty();
+
+                if leading {
+                    warnings.push(Warning {
+                        kind: WarningKind::LeadingLoop,
+                        position,
+                    });
+                }
+
+                opens.push((position, leading));
+            }
+            Instruction::Close => match opens.pop() {
+                Some((start, leading)) => {
+                    if !leading && instructions.back() == Some(&Instruction::Open)
+                    {
+                        warnings.push(Warning {
+                            kind: WarningKind::EmptyLoop,
+                            position: start,
+                        });
+                    }
+                }
+                None => warnings.push(Warning {
+                    kind: WarningKind::UnmatchedClose,
+                    position,
+                }),
+            },
+            _ => {
+                if opens.is_empty() {
+                    touched = true;
+                }
+            }
+        }
+
+        run = match (run, i) {
+            (Some((last, start, count)), _) if last == i => {
+                if count == 255 {
+                    warnings.push(Warning {
+                        kind: WarningKind::WrappingRun,
+                        position: start,
+                    });
+                }
+
+                Some((last, start, count + 1))
+            }
+            (_, Instruction::Add(_)) | (_, Instruction::Sub(_)) => {
+                Some((i, position, 1))
+            }
+            _ => None,
+        };
+
+        instructions.push_back(i);
+    }
+
+    for (position, _) in opens {
+        warnings.push(Warning {
+            kind: WarningKind::UnmatchedOpen,
+            position,
+        });
+    }
+
+    if instructions.is_empty() && bytes.iter().any(|b| !b.is_ascii_whitespace()) {
+        warnings.push(Warning {
+            kind: WarningKind::NoCommands,
+            position: 0,
+        });
+    }
+
+    warnings.sort_by_key(|w| w.position);
+
+    (instructions, warnings)
+}
+
+pub fn parse_checked(bytes: &[u8]) -> Result<VecDeque<Instruction>, ParseError> {
+    let (instructions, warnings) = parse_with_warnings(bytes);
+
+    let error = warnings.iter().find_map(|warning| match warning.kind {
+        WarningKind::UnmatchedOpen => {
+            Some(ParseError::UnmatchedOpen(warning.position))
+        }
+        WarningKind::UnmatchedClose => {
+            Some(ParseError::UnmatchedClose(warning.position))
+        }
+        _ => None,
+    });
+
+    match error {
+        Some(error) => Err(error),
+        None => Ok(instructions),
+    }
+}
+
+pub fn strip_shebang(src: &str) -> &str {
+    if !src.starts_with("#!") {
+        return src;
+    }
+
+    match src.find('\n') {
+        Some(newline) => &src[newline + 1..],
+        None => "",
+    }
+}
+
+/// Collects the text between commands, with each run of whitespace collapsed
+/// to a single space.
+pub fn extract_comments(src: &str) -> String {
+    let comments: String = src
+        .chars()
+        .filter(|&c| !c.is_ascii() || parse_byte(c as u8).is_none())
+        .collect();
+
+    comments.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn parse_with_dialect(src: &str, dialect: &Dialect) -> VecDeque<Instruction> {
+    src.chars().filter_map(|c| dialect.parse_char(c)).collect()
+}
+
+/// Parses whitespace-separated words, skipping any that aren't commands.
+pub fn parse_words(src: &str, dialect: &WordDialect) -> VecDeque<Instruction> {
+    src.split_whitespace()
+        .filter_map(|word| dialect.parse_word(word))
+        .collect()
+}
+
+pub fn parse_ook(src: &str) -> VecDeque<Instruction> {
+    let bytes = src.as_bytes();
+    let mut marks = Vec::new();
+    let mut i = 0;
+
+    while i + 3 < bytes.len() {
+        if &bytes[i..i + 3] == b"Ook"
+            && matches!(bytes[i + 3], b'.' | b'?' | b'!')
+        {
+            marks.push(bytes[i + 3]);
+            i += 4;
+        } else {
+            i += 1;
+        }
+    }
+
+    marks
+        .chunks_exact(2)
+        .filter_map(|pair| parse_ook_pair(pair[0], pair[1]))
+        .collect()
+}
+
+fn parse_ook_pair(a: u8, b: u8) -> Option<Instruction> {
+    match (a, b) {
+        (b'.', b'.') => Some(Instruction::Add(1)),
+        (b'!', b'!') => Some(Instruction::Sub(1)),
+        (b'.', b'?') => Some(Instruction::Right(1)),
+        (b'?', b'.') => Some(Instruction::Left(1)),
+        (b'!', b'.') => Some(Instruction::Out),
+        (b'.', b'!') => Some(Instruction::In),
+        (b'!', b'?') => Some(Instruction::Open),
+        (b'?', b'!') => Some(Instruction::Close),
+        _ => None,
+    }
+}
+
+/// Counting first keeps mostly-comment sources from allocating a slot for
+/// every byte of prose.
+fn command_count(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&b| parse_byte(b).is_some()).count()
+}
+
 fn parse_byte(b: u8) -> Option<Instruction> {
     match b as char {
         '+' => Some(Instruction::Add(1)),
@@ -26,3 +448,332 @@ fn parse_byte(b: u8) -> Option<Instruction> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        extract_comments, parse, parse_checked, parse_ook, parse_reader,
+        parse_with_dialect, parse_with_extensions, parse_with_warnings,
+        parse_words, strip_shebang, Dialect, LineIndex, ParseError, Position,
+        Warning, WarningKind::*, WordDialect,
+    };
+    use std::io::{self, Cursor, Read};
+
+    fn warnings(program: &str) -> Vec<Warning> {
+        parse_with_warnings(program.as_bytes()).1
+    }
+
+    #[test]
+    fn same_instructions_as_parse() {
+        let program = b"[comment]+[->+<]>.";
+
+        assert_eq!(parse(program), parse_with_warnings(program).0);
+    }
+
+    #[test]
+    fn capacity_matches_commands() {
+        let program = "prose without a single command ".repeat(1_000) + "+>.";
+
+        assert!(parse(program.as_bytes()).capacity() < 16);
+        assert!(parse_with_warnings(program.as_bytes()).0.capacity() < 16);
+    }
+
+    #[test]
+    fn no_warnings() {
+        assert_eq!(Vec::<Warning>::new(), warnings("+[->+<]>."));
+    }
+
+    #[test]
+    fn empty_and_leading_loops() {
+        assert_eq!(
+            vec!(
+                Warning {
+                    kind: LeadingLoop,
+                    position: 0
+                },
+                Warning {
+                    kind: EmptyLoop,
+                    position: 13
+                },
+            ),
+            warnings("[a comment] +[]")
+        );
+    }
+
+    #[test]
+    fn wrapping_run() {
+        let warning = Warning {
+            kind: WrappingRun,
+            position: 2,
+        };
+
+        assert_eq!(
+            vec!(warning),
+            warnings(&(">>".to_string() + &"+".repeat(300)))
+        );
+        assert_eq!(
+            vec!(warning),
+            warnings(&("> ".to_string() + &"- ".repeat(256)))
+        );
+        assert!(warnings(&"+".repeat(200)).is_empty());
+        assert!(warnings(&"+".repeat(255)).is_empty());
+        assert!(warnings(&("+".repeat(200) + ">" + &"+".repeat(200))).is_empty());
+        assert!(warnings(&"+-".repeat(200)).is_empty());
+    }
+
+    #[test]
+    fn no_commands() {
+        assert_eq!(
+            vec!(Warning {
+                kind: NoCommands,
+                position: 0
+            }),
+            warnings("just a comment\n")
+        );
+        assert_eq!(Vec::<Warning>::new(), warnings(""));
+        assert_eq!(Vec::<Warning>::new(), warnings(" \n"));
+    }
+
+    #[test]
+    fn unmatched_brackets() {
+        assert_eq!(
+            vec!(
+                Warning {
+                    kind: UnmatchedClose,
+                    position: 1
+                },
+                Warning {
+                    kind: UnmatchedOpen,
+                    position: 3
+                },
+            ),
+            warnings("+]-[+")
+        );
+    }
+
+    #[test]
+    fn line_index_matches_locate() {
+        let program = b"+[\n\n  ]]>\n<\n";
+        let index = LineIndex::new(program);
+
+        for offset in 0..program.len() + 2 {
+            assert_eq!(Position::locate(program, offset), index.locate(offset));
+        }
+
+        assert_eq!(Position { line: 3, column: 4 }, index.locate(7));
+    }
+
+    #[test]
+    fn decimal_output_dialect() {
+        use crate::instruction::Instruction::*;
+
+        let dialect = Dialect {
+            out_decimal: Some(':'),
+            ..Dialect::default()
+        };
+
+        assert_eq!(
+            vec!(Add(1), OutDecimal, Out),
+            Vec::from(parse_with_dialect("+:.", &dialect))
+        );
+        assert_eq!(
+            vec!(Add(1), Out),
+            Vec::from(parse_with_dialect("+:.", &Dialect::default()))
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let program = "Prints A\n\
+                       ++++++++ eight\n\
+                       [>++++++++<-] times eight\n\
+                       >+. plus one and print (café)\n";
+
+        assert_eq!(
+            "Prints A eight times eight plus one and print (café)",
+            extract_comments(program)
+        );
+        assert_eq!("", extract_comments("+[-]>."));
+    }
+
+    #[test]
+    fn reader() {
+        let program = "++[->+<] comment >.,".repeat(1_000);
+
+        assert_eq!(
+            parse(program.as_bytes()),
+            parse_reader(Cursor::new(program.as_bytes())).unwrap()
+        );
+    }
+
+    #[test]
+    fn reader_error() {
+        struct Failing;
+
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("boom"))
+            }
+        }
+
+        assert!(parse_reader(Failing).is_err());
+    }
+
+    #[test]
+    fn position_after_newline() {
+        let program = b"+++\n+]-";
+        let error = parse_checked(program).unwrap_err();
+
+        assert_eq!(ParseError::UnmatchedClose(5), error);
+        assert_eq!(
+            Position { line: 2, column: 2 },
+            Position::locate(program, 5)
+        );
+    }
+
+    #[test]
+    fn position_after_carriage_return() {
+        let program = b"+[-\r\n>]]+\r\n";
+        let error = parse_checked(program).unwrap_err();
+
+        assert_eq!(ParseError::UnmatchedClose(7), error);
+        assert_eq!(
+            Position { line: 2, column: 3 },
+            Position::locate(program, 7)
+        );
+
+        let program = b"+\r+\r]";
+
+        assert_eq!(
+            Position { line: 3, column: 1 },
+            Position::locate(program, 4)
+        );
+        assert_eq!(
+            Position { line: 1, column: 2 },
+            LineIndex::new(program).locate(1)
+        );
+    }
+
+    #[test]
+    fn position_of_newline() {
+        let program = b"+\n\n]";
+
+        assert_eq!(
+            Position { line: 1, column: 2 },
+            Position::locate(program, 1)
+        );
+        assert_eq!(
+            Position { line: 2, column: 1 },
+            Position::locate(program, 2)
+        );
+        assert_eq!(
+            Position { line: 3, column: 1 },
+            Position::locate(program, 3)
+        );
+    }
+
+    #[test]
+    fn extensions() {
+        use crate::instruction::Instruction::*;
+
+        assert_eq!(
+            vec!(Add(1), Ext(b'@'), Out),
+            Vec::from(parse_with_extensions(b"+@.$", b"@"))
+        );
+        assert_eq!(parse(b"+[@]."), parse_with_extensions(b"+[@].", b"["));
+        assert_eq!(parse(b"+ \t."), parse_with_extensions(b"+ \t.", b" \t"));
+    }
+
+    #[test]
+    fn ook() {
+        assert_eq!(
+            parse(b"+-><.,[]"),
+            parse_ook(
+                "Ook. Ook. Ook! Ook! Ook. Ook? Ook? Ook. \
+                 Ook! Ook. Ook. Ook! Ook! Ook? Ook? Ook!"
+            )
+        );
+    }
+
+    #[test]
+    fn ook_ignores_noise() {
+        assert_eq!(
+            parse(b"+>"),
+            parse_ook("Ook.\nOok. (add) Ook?Ook? Ook. Ook? Ook!")
+        );
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(
+            parse(b"+-><.,[]"),
+            parse_words(
+                "incr decr\tnext prev\n  print read loop end",
+                &WordDialect::default()
+            )
+        );
+        assert_eq!(
+            parse(b"+>"),
+            parse_words("incr increment next nextt", &WordDialect::default())
+        );
+    }
+
+    #[test]
+    fn default_dialect() {
+        let program = "++[->+<]>.,";
+
+        assert_eq!(
+            parse(program.as_bytes()),
+            parse_with_dialect(program, &Dialect::default())
+        );
+    }
+
+    #[test]
+    fn custom_dialect() {
+        let dialect = Dialect {
+            add: '-',
+            sub: '+',
+            open: '(',
+            close: ')',
+            ..Dialect::default()
+        };
+
+        assert_eq!(
+            parse(b"-+[>].+-"),
+            parse_with_dialect("+-(>).-+ [ignored]", &dialect)
+        );
+    }
+
+    #[test]
+    fn shebang() {
+        let program = "#!/usr/bin/env -S brainfuck --check\n+[->+<]>.";
+
+        assert_eq!("+[->+<]>.", strip_shebang(program));
+        assert_eq!(
+            parse(b"+[->+<]>."),
+            parse(strip_shebang(program).as_bytes())
+        );
+    }
+
+    #[test]
+    fn no_shebang() {
+        assert_eq!("+# not a shebang\n", strip_shebang("+# not a shebang\n"));
+        assert_eq!("", strip_shebang("#!/usr/bin/env brainfuck"));
+    }
+
+    #[test]
+    fn checked_unmatched_open() {
+        assert_eq!(Err(ParseError::UnmatchedOpen(2)), parse_checked(b"++[>+"));
+        assert_eq!(Err(ParseError::UnmatchedOpen(1)), parse_checked(b"+[[-]>"));
+    }
+
+    #[test]
+    fn checked_unmatched_close() {
+        assert_eq!(Err(ParseError::UnmatchedClose(3)), parse_checked(b"+-.]["));
+    }
+
+    #[test]
+    fn checked_balanced() {
+        assert_eq!(Ok(parse(b"+[->+<]")), parse_checked(b"+[->+<]"));
+    }
+}
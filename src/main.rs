@@ -1,41 +1,312 @@
-use brainfuck::{Brainfuck, Error};
+use brainfuck::{
+    assemble, disassemble, parse_with_warnings, strip_shebang, Brainfuck,
+    CellFormat, Error, LineIndex, WarningKind,
+};
 use std::{
-    env,
-    fs::File,
+    cmp, env,
+    fs::{self, File},
     io::{self, Read, Write},
     path::Path,
+    process,
+    time::Instant,
 };
 
+const DUMP_ROW: usize = 16;
+
+const USAGE: &str = "Usage: brainfuck [OPTIONS] [PROGRAM...]
+
+Runs the brainfuck PROGRAM files, concatenated in order. Without a program,
+starts an interactive REPL.
+
+Options:
+  --input <PATH>      read program input from PATH instead of stdin
+  --dump-tape <N>     print the first N tape cells after running
+  --cell-format <F>   show dumped cells as decimal (default), hex or ascii
+  --check             only check brackets; exit with 1 if unbalanced
+  --stats             print steps, high water mark and timing after running
+  --emit-ir <PATH>    write optimized instructions to PATH instead of running
+  --run-ir <PATH>     run instructions previously written with --emit-ir
+  --repl              start the REPL even when a program is given
+  --help              show this message
+  --version           show the version";
+
+const REPL_HELP: &str = "Each line is run against the same tape.
+Commands:
+  :reset  clear the tape and move the pointer back to cell 0
+  :help   show this message
+  :quit   leave the REPL (end of input works too)
+Programs that read input with ',' need --input, as stdin holds the commands.";
+
+const REPL_NEEDS_INPUT: &str =
+    "Error: ',' needs --input in the REPL, as stdin holds the commands.";
+
 fn main() -> io::Result<()> {
     let mut args = env::args();
 
     let _ = args.next();
-    let path = args.next().expect("pass file!");
-    let mut program = String::new();
+    let mut paths = Vec::new();
+    let mut input_path = None;
+    let mut dump_tape = None;
+    let mut check = false;
+    let mut repl = false;
+    let mut stats = false;
+    let mut emit_ir = None;
+    let mut run_ir = None;
+    let mut cell_format = CellFormat::default();
 
-    let mut f = File::open(&Path::new(&path))?;
-    f.read_to_string(&mut program)?;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| missing_value(&arg));
+
+        match arg.as_str() {
+            "--input" => input_path = Some(value()),
+            "--dump-tape" => {
+                let cells =
+                    value().parse().unwrap_or_else(|_| missing_value(&arg));
+                dump_tape = Some(cells);
+            }
+            "--cell-format" => {
+                cell_format = match value().as_str() {
+                    "decimal" => CellFormat::Decimal,
+                    "hex" => CellFormat::Hex,
+                    "ascii" => CellFormat::Ascii,
+                    _ => missing_value(&arg),
+                };
+            }
+            "--check" => check = true,
+            "--repl" => repl = true,
+            "--stats" => stats = true,
+            "--emit-ir" => emit_ir = Some(value()),
+            "--run-ir" => run_ir = Some(value()),
+            "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            "--version" => {
+                println!("brainfuck {}", env!("CARGO_PKG_VERSION"));
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown option {}. Run with --help for usage.", arg);
+                process::exit(2);
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    if check && paths.is_empty() {
+        eprintln!("--check needs a program to check. Run with --help for usage.");
+        process::exit(2);
+    }
 
     let mut stderr = io::stderr();
-    let mut stdin = io::stdin();
+    let input_from_file = input_path.is_some();
+    let mut input: Box<dyn Read> = match input_path {
+        Some(input_path) => Box::new(File::open(Path::new(&input_path))?),
+        None => Box::new(io::stdin()),
+    };
+
+    let mut brainfuck = match run_ir {
+        Some(ir_path) => load_ir(&ir_path, &mut stderr)?,
+        None if paths.is_empty() || repl => {
+            return run_repl(input_from_file.then_some(&mut input))
+        }
+        None => load_sources(&paths, check, &mut stderr)?,
+    };
+
+    brainfuck.set_cell_format(cell_format);
+
+    if let Some(ir_path) = emit_ir {
+        return fs::write(ir_path, disassemble(brainfuck.instructions()));
+    }
+
     let mut stdout = io::stdout();
 
-    let mut brainfuck = Brainfuck::new(&program);
-    let result = brainfuck.run(&mut stdin, &mut stdout);
+    let started = Instant::now();
+    let result = brainfuck.run(&mut input, &mut stdout);
+    let elapsed = started.elapsed();
 
-    match result {
-        Err(Error::ReadError(err)) => {
-            writeln!(stderr, "Read error: {:?}.", err)?;
+    let failed = report(&mut stderr, &brainfuck, result)?;
+
+    if stats {
+        writeln!(stderr, "Steps: {}", brainfuck.step_count())?;
+        writeln!(stderr, "High water mark: {}", brainfuck.high_water_mark())?;
+        writeln!(stderr, "Final pointer: {}", brainfuck.tape_pointer())?;
+        writeln!(stderr, "Elapsed: {:?}", elapsed)?;
+    }
+
+    if let Some(cells) = dump_tape {
+        dump(&mut stderr, &brainfuck, cells)?;
+    }
+
+    if failed {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Gives up on a `flag` whose value is missing or invalid.
+fn missing_value(flag: &str) -> ! {
+    eprintln!("{} needs a value. Run with --help for usage.", flag);
+    process::exit(2);
+}
+
+fn load_sources<W: Write>(
+    paths: &[String],
+    check: bool,
+    stderr: &mut W,
+) -> io::Result<Brainfuck> {
+    let mut program = String::new();
+    let mut source = String::new();
+    // Where each file's code starts, in `source` and in `program`.
+    let mut starts = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        let text = fs::read_to_string(path)?;
+        let code = strip_shebang(&text);
+
+        starts.push((source.len(), program.len() + text.len() - code.len()));
+        source.push_str(code);
+        program.push_str(&text);
+    }
+
+    let mut unbalanced = false;
+    let lines = LineIndex::new(program.as_bytes());
+
+    for warning in parse_with_warnings(source.as_bytes()).1 {
+        let message = match warning.kind {
+            WarningKind::UnmatchedOpen => "unmatched '['",
+            WarningKind::UnmatchedClose => "unmatched ']'",
+            WarningKind::EmptyLoop => "empty loop",
+            WarningKind::LeadingLoop => "loop never executed",
+            WarningKind::NoCommands => "no commands found",
+            WarningKind::WrappingRun => "more than 255 '+' or '-' in a row",
+        };
+
+        let (start, offset) = starts
+            .iter()
+            .rev()
+            .find(|&&(start, _)| start <= warning.position)
+            .copied()
+            .unwrap_or((0, 0));
+        let byte = warning.position - start + offset;
+        let position = lines.locate(byte);
+
+        writeln!(
+            stderr,
+            "Warning: {} at byte {} (line {}, column {}).",
+            message, byte, position.line, position.column
+        )?;
+
+        unbalanced |= warning.kind == WarningKind::UnmatchedOpen
+            || warning.kind == WarningKind::UnmatchedClose;
+    }
+
+    if check {
+        process::exit(if unbalanced { 1 } else { 0 });
+    }
+
+    Ok(Brainfuck::new(&source))
+}
+
+fn load_ir<W: Write>(path: &str, stderr: &mut W) -> io::Result<Brainfuck> {
+    match assemble(&fs::read_to_string(path)?) {
+        Ok(instructions) => Ok(Brainfuck::from_instructions(instructions)),
+        Err(err) => {
+            writeln!(stderr, "Invalid IR: {}.", err)?;
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs each line of stdin as a program. Since stdin holds the commands,
+/// programs only get input, through `,`, from the file given with `--input`.
+fn run_repl<R: Read>(mut input: Option<&mut R>) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut stderr = io::stderr();
+    let mut brainfuck = Brainfuck::new("");
+    let mut line = String::new();
+
+    writeln!(stderr, "{}", REPL_HELP)?;
+
+    loop {
+        write!(stderr, "> ")?;
+
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            break;
         }
+
+        match line.trim() {
+            ":reset" => brainfuck.reset(),
+            ":help" => writeln!(stderr, "{}", REPL_HELP)?,
+            ":quit" => break,
+            program if input.is_none() && program.contains(',') => {
+                writeln!(stderr, "{}", REPL_NEEDS_INPUT)?;
+            }
+            program => {
+                brainfuck.load(program);
+                let result = match input {
+                    Some(ref mut input) => brainfuck.run(input, &mut stdout),
+                    None => brainfuck.run(&mut io::empty(), &mut stdout),
+                };
+                stdout.flush()?;
+
+                let _ = report(&mut stderr, &brainfuck, result)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Describes a failed run on `out`, and tells whether it failed. A closed
+/// stdout is not a failure.
+fn report<W: Write>(
+    out: &mut W,
+    brainfuck: &Brainfuck,
+    result: brainfuck::Result,
+) -> io::Result<bool> {
+    match result {
         Err(Error::WriteError(ref err))
-            if err.kind() != io::ErrorKind::BrokenPipe =>
+            if err.kind() == io::ErrorKind::BrokenPipe =>
         {
-            writeln!(stderr, "Write error: {:?}.", err)?;
+            Ok(false)
         }
-        Err(Error::UnbalancedParens) => {
-            writeln!(stderr, "Unbalanced parens found.")?;
+        Err(err) => {
+            writeln!(out, "Error: {}.", brainfuck.describe(&err))?;
+            Ok(true)
         }
-        _ => {}
+        Ok(()) => Ok(false),
+    }
+}
+
+fn dump<W: Write>(
+    out: &mut W,
+    brainfuck: &Brainfuck,
+    cells: usize,
+) -> io::Result<()> {
+    let high_water = brainfuck.high_water_mark();
+    let cells = cmp::min(cells, brainfuck.tape_len());
+
+    writeln!(
+        out,
+        "Tape (pointer {}, high water mark {}):",
+        brainfuck.tape_pointer(),
+        high_water
+    )?;
+
+    for start in (0..cells).step_by(DUMP_ROW) {
+        let end = cmp::min(start + DUMP_ROW, cells);
+
+        write!(out, "{:>5}:", start)?;
+
+        for &cell in brainfuck.tape(start..end) {
+            write!(out, " {:>3}", brainfuck.cell_format().format(cell))?;
+        }
+
+        writeln!(out)?;
     }
 
     Ok(())
@@ -0,0 +1,382 @@
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+fn brainfuck() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+}
+
+/// A file in the temp dir that is removed again when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> TempFile {
+        TempFile(env::temp_dir().join(format!(
+            "brainfuck-cli-{}-{}",
+            std::process::id(),
+            name
+        )))
+    }
+}
+
+impl AsRef<OsStr> for TempFile {
+    fn as_ref(&self) -> &OsStr {
+        self.0.as_os_str()
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn temp_file(name: &str, contents: &[u8]) -> TempFile {
+    let file = TempFile::new(name);
+    fs::write(&file.0, contents).unwrap();
+    file
+}
+
+#[test]
+fn input_from_file() {
+    let program = temp_file("echo.bf", b",[.,]");
+    let input = temp_file("echo.in", b"echo me");
+
+    let output = brainfuck()
+        .arg("--input")
+        .arg(&input)
+        .arg(&program)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(b"echo me", output.stdout.as_slice());
+}
+
+#[test]
+fn dump_tape() {
+    let program = temp_file("dump.bf", b"+++>+");
+
+    let output = brainfuck()
+        .arg("--dump-tape")
+        .arg("2")
+        .arg(&program)
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let row = stderr
+        .lines()
+        .find(|line| line.trim_start().starts_with("0:"))
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        vec!("0:", "3", "1"),
+        row.split_whitespace().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn check_only() {
+    let unbalanced = temp_file("unbalanced.bf", b"[[]");
+    let balanced = temp_file("balanced.bf", b"+[]");
+
+    let output = brainfuck()
+        .arg("--check")
+        .arg(&unbalanced)
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unmatched '[' at byte 0 (line 1, column 1)"));
+
+    let output = brainfuck().arg("--check").arg(&balanced).output().unwrap();
+
+    assert!(output.status.success());
+}
+
+#[test]
+fn check_without_program() {
+    let output = brainfuck().arg("--check").output().unwrap();
+
+    assert_eq!(Some(2), output.status.code());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("--check needs a program to check."));
+}
+
+#[test]
+fn runtime_error() {
+    let program = temp_file("runtime_error.bf", b"[-][+.>");
+
+    let output = brainfuck().arg(&program).output().unwrap();
+
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Error: unbalanced parentheses."));
+}
+
+#[test]
+fn repl() {
+    let mut child = brainfuck()
+        .arg("--repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"++.\n>+.<.\n:reset\n+.\n")
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(vec!(2, 1, 2, 1), output.stdout);
+}
+
+#[test]
+fn repl_input() {
+    let repl = |args: &[&OsStr]| {
+        let mut child = brainfuck()
+            .arg("--repl")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        child.stdin.take().unwrap().write_all(b",.\n+.\n").unwrap();
+        child.wait_with_output().unwrap()
+    };
+
+    let output = repl(&[]);
+
+    assert!(output.status.success());
+    assert_eq!(vec!(1), output.stdout);
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("',' needs --input in the REPL"));
+
+    let input = temp_file("repl.in", b"A");
+    let output = repl(&[OsStr::new("--input"), input.as_ref()]);
+
+    assert!(output.status.success());
+    assert_eq!(b"AB", output.stdout.as_slice());
+}
+
+#[test]
+fn shebang() {
+    let program = temp_file(
+        "shebang.bf",
+        b"#!/usr/bin/env -S brainfuck --dump-tape 1\n++.[",
+    );
+
+    let output = brainfuck().arg(&program).output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(vec!(2), output.stdout);
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unmatched '[' at byte 45"));
+}
+
+#[test]
+fn stats() {
+    let program = temp_file("stats.bf", b"++[->++<]>");
+
+    let output = brainfuck().arg("--stats").arg(&program).output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success());
+    assert!(stderr.contains("Steps: 13"));
+    assert!(stderr.contains("High water mark: 1"));
+    assert!(stderr.contains("Final pointer: 1"));
+    assert!(stderr.contains("Elapsed: "));
+}
+
+#[test]
+fn multiple_files() {
+    let first = temp_file("first.bf", b"++++++++[>++++++++");
+    let second = temp_file("second.bf", b"<-]>+.");
+
+    let output = brainfuck().arg(&first).arg(&second).output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(b"A", output.stdout.as_slice());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn multiple_files_with_shebangs() {
+    let first = temp_file("first-shebang.bf", b"++++++++[>++++++++");
+    let second = temp_file(
+        "second-shebang.bf",
+        b"#!/usr/bin/env -S brainfuck --dump-tape 1\n<-]>+.",
+    );
+
+    let output = brainfuck().arg(&first).arg(&second).output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(b"A", output.stdout.as_slice());
+    assert!(output.stderr.is_empty());
+
+    let third = temp_file("third-shebang.bf", b"#!/usr/bin/env brainfuck\n]]");
+
+    let output = brainfuck().arg(&first).arg(&third).output().unwrap();
+
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unmatched ']' at byte 44 (line 2, column 2)"));
+}
+
+#[test]
+fn comments_only() {
+    let program = temp_file("comments.bf", b"this is not the program you want\n");
+
+    let output = brainfuck().arg(&program).output().unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Warning: no commands found at byte 0"));
+}
+
+#[test]
+fn emit_and_run_ir() {
+    let program = temp_file(
+        "hello.bf",
+        b"++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---\
+          .+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.",
+    );
+    let ir = TempFile::new("hello.ir");
+
+    let output = brainfuck()
+        .arg("--emit-ir")
+        .arg(&ir)
+        .arg(&program)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(fs::read_to_string(&ir)
+        .unwrap()
+        .starts_with("ADD 8\nOPEN\n"));
+
+    let output = brainfuck().arg("--run-ir").arg(&ir).output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(b"Hello World!\n", output.stdout.as_slice());
+}
+
+#[test]
+fn invalid_ir() {
+    let ir = temp_file("invalid.ir", b"ADD 1\nJUMP 3\n");
+
+    let output = brainfuck().arg("--run-ir").arg(&ir).output().unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("invalid instruction `JUMP 3` on line 2"));
+}
+
+#[test]
+fn help() {
+    let output = brainfuck().arg("--help").output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert!(stdout.starts_with("Usage: brainfuck"));
+    assert!(stdout.contains("--dump-tape <N>"));
+}
+
+#[test]
+fn version() {
+    let output = brainfuck().arg("--version").output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        format!("brainfuck {}\n", env!("CARGO_PKG_VERSION")),
+        String::from_utf8(output.stdout).unwrap()
+    );
+}
+
+#[test]
+fn unknown_option() {
+    let output = brainfuck().arg("--frobnicate").output().unwrap();
+
+    assert_eq!(Some(2), output.status.code());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Unknown option --frobnicate. Run with --help for usage."));
+}
+
+#[test]
+fn missing_value() {
+    let output = brainfuck().arg("--input").output().unwrap();
+
+    assert_eq!(Some(2), output.status.code());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("--input needs a value. Run with --help for usage."));
+
+    let output = brainfuck()
+        .arg("--cell-format")
+        .arg("bogus")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(2), output.status.code());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("--cell-format needs a value. Run with --help for usage."));
+}
+
+#[test]
+fn dump_tape_hex() {
+    let program = temp_file("dump-hex.bf", b"+++++++++++>+");
+
+    let output = brainfuck()
+        .arg("--dump-tape")
+        .arg("2")
+        .arg("--cell-format")
+        .arg("hex")
+        .arg(&program)
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let row = stderr
+        .lines()
+        .find(|line| line.trim_start().starts_with("0:"))
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        vec!("0:", "0b", "01"),
+        row.split_whitespace().collect::<Vec<_>>()
+    );
+}